@@ -1,12 +1,129 @@
 // Migrations are an early feature. Currently, they're nothing more than this
 // single deploy script that's invoked from the CLI, injecting a provider
 // configured from the workspace's Anchor.toml.
+//
+// Sets up a freshly deployed program so challenges can be opened: the protocol
+// config and vaults, every stats shard and the default challenge types. Steps
+// whose accounts already exist are skipped, so it is safe to run again.
+//
+//   ACCEPTED_MINT=<USDT mint> TREASURY=<treasury wallet> anchor migrate
 
 import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { Disciplinator } from "../target/types/disciplinator";
+import { PublicKey, SystemProgram, SYSVAR_RENT_PUBKEY } from "@solana/web3.js";
+
+// Mirrors STATS_SHARD_COUNT in the program
+const STATS_SHARD_COUNT = 8;
+
+// Ids are what create_challenge references, so they must stay stable
+const DEFAULT_CHALLENGE_TYPES = [
+  { id: 0, name: "Fitness", schema: { fitness: {} }, minSessionMinutes: 20 },
+  { id: 1, name: "Education", schema: { education: {} }, minSessionMinutes: 30 },
+  { id: 2, name: "Meditation", schema: { meditation: {} }, minSessionMinutes: 10 },
+  { id: 3, name: "Custom", schema: { custom: {} }, minSessionMinutes: 0 },
+];
+
+const requiredEnv = (name: string): string => {
+  const value = process.env[name];
+  if (!value) {
+    throw new Error(`${name} must be set to initialize the protocol`);
+  }
+  return value;
+};
 
 module.exports = async function (provider: anchor.AnchorProvider) {
   // Configure client to use the provider.
   anchor.setProvider(provider);
 
-  // Add your deploy script here.
+  const program = anchor.workspace.Disciplinator as Program<Disciplinator>;
+  const authority = provider.wallet.publicKey;
+  const pda = (...seeds: Buffer[]) => PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+  const exists = async (address: PublicKey) =>
+    (await provider.connection.getAccountInfo(address)) !== null;
+
+  const configPda = pda(Buffer.from("config"));
+  if (!(await exists(configPda))) {
+    const acceptedMint = new PublicKey(requiredEnv("ACCEPTED_MINT"));
+    const treasury = new PublicKey(requiredEnv("TREASURY"));
+    // The vaults are created under whichever token program owns the mint
+    const mintInfo = await provider.connection.getAccountInfo(acceptedMint);
+    if (!mintInfo) {
+      throw new Error(`Mint ${acceptedMint.toBase58()} not found`);
+    }
+
+    await program.methods
+      .initialize(20, 70, 10) // 20% fee, 70% rewards, 10% charity
+      .accounts({
+        config: configPda,
+        authority,
+        treasury,
+        acceptedMint,
+        vault: pda(Buffer.from("vault"), configPda.toBuffer()),
+        vaultRewards: pda(Buffer.from("vault_rewards"), configPda.toBuffer()),
+        vaultReserve: pda(Buffer.from("vault_reserve"), configPda.toBuffer()),
+        rewardState: pda(Buffer.from("reward_state")),
+        tokenProgram: mintInfo.owner,
+        systemProgram: SystemProgram.programId,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+    console.log("Initialized config", configPda.toBase58());
+  }
+
+  // Every challenge writes to the shard of its participant, so all of them must exist
+  for (let index = 0; index < STATS_SHARD_COUNT; index++) {
+    const statsShardPda = pda(Buffer.from("stats_shard"), Buffer.from([index]));
+    if (await exists(statsShardPda)) {
+      continue;
+    }
+    await program.methods
+      .initStatsShard(index)
+      .accounts({
+        statsShard: statsShardPda,
+        config: configPda,
+        authority,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    console.log("Initialized stats shard", index);
+  }
+
+  // Types start with the protocol's full deposit range; narrow them later with update_challenge_type
+  const config = await program.account.config.fetch(configPda);
+  for (const challengeType of DEFAULT_CHALLENGE_TYPES) {
+    const challengeTypePda = pda(
+      Buffer.from("challenge_type"),
+      new anchor.BN(challengeType.id).toArrayLike(Buffer, "le", 2)
+    );
+    if (await exists(challengeTypePda)) {
+      continue;
+    }
+    await program.methods
+      .createChallengeType(challengeType.id, {
+        name: challengeType.name,
+        schema: challengeType.schema,
+        minSessionMinutes: challengeType.minSessionMinutes,
+        minDeposit: config.minDeposit,
+        maxDeposit: config.maxDeposit,
+        defaultGracePeriods: 3,
+        maxGracePeriods: 5,
+        defaultGraceDays: 3,
+        maxGraceDays: 7,
+        graceRefundPenaltyBps: 0,
+        minSessions: 1,
+        maxSessions: 365,
+        minDurationDays: 7,
+        maxDurationDays: 365,
+        active: true,
+      })
+      .accounts({
+        challengeType: challengeTypePda,
+        config: configPda,
+        authority,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    console.log("Created challenge type", challengeType.name);
+  }
 };
@@ -0,0 +1,295 @@
+//! IPFS content identifier parsing.
+//!
+//! Accepts CIDv0 (`Qm...`) and CIDv1 strings in the multibases IPFS tooling
+//! actually emits, and reduces them to the binary CID form stored on-chain:
+//! the bare multihash for v0, `<version><codec><multihash>` for v1.
+
+/// Largest binary CID we accept: v1 + 2-byte codec + 3-byte hash code + length + 64-byte digest.
+pub const MAX_BINARY_LEN: usize = 1 + 2 + 3 + 1 + 64;
+
+/// Longest text form we are willing to decode; bounds base58 compute cost.
+const MAX_TEXT_LEN: usize = 160;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Multicodec content types accepted for CIDv1.
+pub mod codec {
+    pub const RAW: u64 = 0x55;
+    pub const DAG_PB: u64 = 0x70;
+    pub const DAG_CBOR: u64 = 0x71;
+    pub const DAG_JSON: u64 = 0x0129;
+}
+
+/// Multihash functions accepted in either CID version.
+pub mod multihash {
+    pub const SHA2_256: u64 = 0x12;
+    pub const SHA2_512: u64 = 0x13;
+    pub const SHA3_256: u64 = 0x16;
+    pub const BLAKE3: u64 = 0x1e;
+    pub const BLAKE2B_256: u64 = 0xb220;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CidError {
+    Empty,
+    TooLong,
+    UnsupportedMultibase,
+    InvalidEncoding,
+    InvalidVarint,
+    UnsupportedVersion,
+    UnsupportedCodec,
+    UnsupportedHash,
+    DigestLengthMismatch,
+}
+
+/// Parses a CID string and returns its binary form.
+pub fn parse(text: &str) -> Result<Vec<u8>, CidError> {
+    if text.is_empty() {
+        return Err(CidError::Empty);
+    }
+    if text.len() > MAX_TEXT_LEN {
+        return Err(CidError::TooLong);
+    }
+
+    // CIDv0 has no multibase prefix: it is always a base58btc sha2-256 multihash
+    if text.len() == 46 && text.starts_with("Qm") {
+        let bytes = decode_base58(text)?;
+        validate_v0(&bytes)?;
+        return Ok(bytes);
+    }
+
+    let mut chars = text.chars();
+    let prefix = chars.next();
+    let body = chars.as_str();
+    let bytes = match prefix {
+        Some('b') => decode_base32(body)?,
+        Some('B') => decode_base32(&body.to_ascii_lowercase())?,
+        Some('z') => decode_base58(body)?,
+        Some('f' | 'F') => decode_base16(body)?,
+        _ => return Err(CidError::UnsupportedMultibase),
+    };
+    validate_binary(&bytes)?;
+    Ok(bytes)
+}
+
+/// Validates a binary CID of either version.
+pub fn validate_binary(bytes: &[u8]) -> Result<(), CidError> {
+    if bytes.len() > MAX_BINARY_LEN {
+        return Err(CidError::TooLong);
+    }
+    if validate_v0(bytes).is_ok() {
+        return Ok(());
+    }
+
+    let mut pos = 0;
+    if read_varint(bytes, &mut pos)? != 1 {
+        return Err(CidError::UnsupportedVersion);
+    }
+    match read_varint(bytes, &mut pos)? {
+        codec::RAW | codec::DAG_PB | codec::DAG_CBOR | codec::DAG_JSON => {}
+        _ => return Err(CidError::UnsupportedCodec),
+    }
+    validate_multihash(&bytes[pos..])
+}
+
+fn validate_v0(bytes: &[u8]) -> Result<(), CidError> {
+    if bytes.len() == 34 && bytes[0] == multihash::SHA2_256 as u8 && bytes[1] == 32 {
+        Ok(())
+    } else {
+        Err(CidError::UnsupportedVersion)
+    }
+}
+
+fn validate_multihash(bytes: &[u8]) -> Result<(), CidError> {
+    let mut pos = 0;
+    let expected_len = match read_varint(bytes, &mut pos)? {
+        multihash::SHA2_256 | multihash::SHA3_256 | multihash::BLAKE3 | multihash::BLAKE2B_256 => 32,
+        multihash::SHA2_512 => 64,
+        _ => return Err(CidError::UnsupportedHash),
+    };
+    let digest_len = read_varint(bytes, &mut pos)?;
+    if digest_len != expected_len || bytes.len() - pos != expected_len as usize {
+        return Err(CidError::DigestLengthMismatch);
+    }
+    Ok(())
+}
+
+/// Reads an unsigned LEB128 varint, rejecting non-minimal encodings as the
+/// multiformats spec requires.
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, CidError> {
+    let mut value: u64 = 0;
+    for shift in 0..9 {
+        let byte = *bytes.get(*pos).ok_or(CidError::InvalidVarint)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << (shift * 7);
+        if byte & 0x80 == 0 {
+            if byte == 0 && shift > 0 {
+                return Err(CidError::InvalidVarint);
+            }
+            return Ok(value);
+        }
+    }
+    Err(CidError::InvalidVarint)
+}
+
+fn decode_base58(text: &str) -> Result<Vec<u8>, CidError> {
+    // Big-endian base-256 accumulator, multiplied by 58 per input digit
+    let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+    for c in text.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or(CidError::InvalidEncoding)? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, (carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let leading_zeros = text.bytes().take_while(|&c| c == b'1').count();
+    let mut out = vec![0u8; leading_zeros];
+    out.extend(bytes);
+    Ok(out)
+}
+
+fn decode_base32(text: &str) -> Result<Vec<u8>, CidError> {
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or(CidError::InvalidEncoding)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // Unpadded RFC 4648: leftover bits must be fewer than a byte and all zero
+    if bits >= 5 || buffer != 0 {
+        return Err(CidError::InvalidEncoding);
+    }
+    Ok(out)
+}
+
+fn decode_base16(text: &str) -> Result<Vec<u8>, CidError> {
+    if !text.len().is_multiple_of(2) {
+        return Err(CidError::InvalidEncoding);
+    }
+    let nibble = |c: u8| match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(CidError::InvalidEncoding),
+    };
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| Ok((nibble(pair[0])? << 4) | nibble(pair[1])?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sha2-256 of the empty string, the example pair from the CID specification
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const V0_EMPTY: &str = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n";
+    const V1_EMPTY_DAG_PB: &str = "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+    // raw sha2-256 CID of "hello world" in several multibases
+    const HELLO_SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    const V1_HELLO_RAW_BASE32: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+    const V1_HELLO_RAW_BASE58: &str = "zb2rhj7crUKTQYRGCRATFaQ6YFLTde2YzdqbbhAASkL9uRDXn";
+
+    fn hex(text: &str) -> Vec<u8> {
+        decode_base16(text).unwrap()
+    }
+
+    #[test]
+    fn parses_cid_v0() {
+        let bytes = parse(V0_EMPTY).unwrap();
+        let mut expected = vec![0x12, 0x20];
+        expected.extend(hex(EMPTY_SHA256));
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn parses_cid_v1_dag_pb_matching_v0() {
+        let v0 = parse(V0_EMPTY).unwrap();
+        let v1 = parse(V1_EMPTY_DAG_PB).unwrap();
+        assert_eq!(&v1[..2], &[0x01, 0x70]);
+        assert_eq!(&v1[2..], &v0[..]);
+    }
+
+    #[test]
+    fn parses_cid_v1_across_multibases() {
+        let mut expected = vec![0x01, 0x55, 0x12, 0x20];
+        expected.extend(hex(HELLO_SHA256));
+
+        assert_eq!(parse(V1_HELLO_RAW_BASE32).unwrap(), expected);
+        assert_eq!(parse(&V1_HELLO_RAW_BASE32.to_ascii_uppercase()).unwrap(), expected);
+        assert_eq!(parse(V1_HELLO_RAW_BASE58).unwrap(), expected);
+        assert_eq!(parse(&format!("f01551220{}", HELLO_SHA256)).unwrap(), expected);
+    }
+
+    #[test]
+    fn parses_sha2_512_dag_cbor() {
+        let cid = "bafyrgqbqt3gerhas23vuzrapkdeqf4vu2dwxp3srdj6hvg6nhsug2tgyn6mj3u23yx7utftq3i2ckw2fwdh5qmhid5qf3t35yvkc5e5ottlw6";
+        let bytes = parse(cid).unwrap();
+        assert_eq!(&bytes[..4], &[0x01, 0x71, 0x13, 0x40]);
+        assert_eq!(bytes.len(), 68);
+    }
+
+    #[test]
+    fn rejects_malformed_text() {
+        assert_eq!(parse(""), Err(CidError::Empty));
+        assert_eq!(parse("invalid_hash"), Err(CidError::UnsupportedMultibase));
+        assert_eq!(parse("éafy"), Err(CidError::UnsupportedMultibase));
+        assert_eq!(parse("Qm0000000000000000000000000000000000000000000O"), Err(CidError::InvalidEncoding));
+        assert_eq!(parse("bafy!"), Err(CidError::InvalidEncoding));
+        assert_eq!(parse("f0155122"), Err(CidError::InvalidEncoding));
+        assert_eq!(parse(&"b".repeat(MAX_TEXT_LEN + 1)), Err(CidError::TooLong));
+    }
+
+    #[test]
+    fn rejects_unsupported_cid_contents() {
+        // version 2
+        assert_eq!(parse(&format!("f02551220{}", HELLO_SHA256)), Err(CidError::UnsupportedVersion));
+        // git-raw codec
+        assert_eq!(parse(&format!("f01781220{}", HELLO_SHA256)), Err(CidError::UnsupportedCodec));
+        // identity multihash
+        assert_eq!(parse("f0155000568656c6c6f"), Err(CidError::UnsupportedHash));
+        // digest shorter than its declared length
+        assert_eq!(parse(&format!("f01551220{}", &HELLO_SHA256[..62])), Err(CidError::DigestLengthMismatch));
+        // declared length disagrees with the hash function
+        assert_eq!(parse(&format!("f01551210{}", &HELLO_SHA256[..32])), Err(CidError::DigestLengthMismatch));
+    }
+
+    #[test]
+    fn rejects_non_minimal_varints() {
+        let mut pos = 0;
+        assert_eq!(read_varint(&[0x81, 0x00], &mut pos), Err(CidError::InvalidVarint));
+        let mut pos = 0;
+        assert_eq!(read_varint(&[0xa9, 0x02], &mut pos), Ok(codec::DAG_JSON));
+        assert_eq!(pos, 2);
+    }
+
+    #[test]
+    fn max_binary_len_covers_largest_supported_cid() {
+        // v1 + dag-json (2-byte codec varint) + sha2-512 (64-byte digest)
+        let mut bytes = vec![0x01, 0xa9, 0x02, 0x13, 0x40];
+        bytes.extend([0u8; 64]);
+        assert!(bytes.len() <= MAX_BINARY_LEN);
+        assert_eq!(validate_binary(&bytes), Ok(()));
+    }
+}
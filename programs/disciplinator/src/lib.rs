@@ -1,10 +1,29 @@
 #![allow(unexpected_cfgs)]
+// Only for the IDL handlers `#[program]` emits at the crate root, which still call the
+// deprecated `AccountInfo::realloc`; an attribute on the program module does not reach them
+#![allow(deprecated)]
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::system_program;
 use anchor_spl::token_2022::{self};
 use anchor_spl::token_interface::{TokenAccount, TokenInterface, TransferChecked};
 use anchor_spl::token::Mint;
 
+pub mod cid;
+pub mod math;
+pub mod merkle;
+pub mod metadata;
+pub mod proof;
+pub mod schedule;
+
+use metadata::{validate_session_goal, validate_session_metadata, MetadataSchema, SessionGoal, SessionMetadata};
+use merkle::SESSION_TREE_DEPTH;
+use proof::{ProofInput, ProofRef};
+use schedule::{Schedule, RECENT_SESSIONS};
+
 declare_id!("Em4efpnH5X51Gr5hSKKWwJ4K2ktgcKDh5qgqr2w54WSH");
 
 #[program]
@@ -34,7 +53,7 @@ pub mod disciplinator {
             ];
             
             let is_valid_usdt = valid_mints.iter().any(|&mint| {
-                mint.parse::<Pubkey>().map_or(false, |parsed_mint| parsed_mint == mint_key)
+                mint.parse::<Pubkey>().is_ok_and(|parsed_mint| parsed_mint == mint_key)
             });
             
             require!(is_valid_usdt, ErrorCode::InvalidMint);
@@ -52,48 +71,60 @@ pub mod disciplinator {
         config.paused = false;
         config.min_deposit = 5_000_000; // 5 USDT minimum
         config.max_deposit = 10_000_000_000; // 10,000 USDT maximum
+        config.arbiter = ctx.accounts.authority.key(); // Authority arbitrates until delegated
+        config.grace_price_base = 1_000_000; // 1 USDT for the first extra grace period
+        config.grace_price_increment = 1_000_000; // +1 USDT for each further one
+        config.max_grace_purchases = 3;
+        config.max_start_delay_days = 14;
+        config.min_milestone_step_bps = 1000; // Refund slices no finer than 10% of sessions
+        config.max_pause_days = 30;
         
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_challenge(
         ctx: Context<CreateChallenge>,
         deposit_amount: u64,
         total_sessions: u32,
         duration_days: u32,
         verifier: Option<Pubkey>,
-        challenge_type: ChallengeType,
+        challenge_type_id: u16,
+        session_goal: SessionGoal,
+        schedule: Option<Schedule>,
+        grace_terms: Option<GraceTerms>,
+        start_time: Option<i64>,
+        phase_targets: Option<Vec<u32>>,
+        milestone_step_bps: Option<u16>,
     ) -> Result<()> {
-        let challenge = &mut ctx.accounts.challenge;
-        let config = &mut ctx.accounts.config;
         let clock = Clock::get()?;
+        open_challenge(
+            &mut ctx.accounts.challenge,
+            &mut ctx.accounts.user_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.challenge_type,
+            ctx.accounts.participant.key(),
+            ChallengeTerms {
+                deposit_amount,
+                total_sessions,
+                duration_days,
+                verifier,
+                challenge_type_id,
+                session_goal,
+                schedule,
+                grace_terms,
+                grace_refund_penalty_bps: None,
+                start_time,
+                phase_targets,
+                milestone_step_bps,
+            },
+            clock.unix_timestamp,
+        )?;
+        let challenge = &ctx.accounts.challenge;
         
-        // Validate inputs
-        require!(!config.paused, ErrorCode::ProtocolPaused);
-        require!(deposit_amount >= config.min_deposit, ErrorCode::DepositTooSmall); // Min from config
-        require!(deposit_amount <= config.max_deposit, ErrorCode::DepositTooLarge); // Max from config
-        require!(total_sessions > 0 && total_sessions <= 365, ErrorCode::InvalidSessionCount);
-        require!(duration_days >= 7 && duration_days <= 365, ErrorCode::InvalidDuration);
-        
-        // Initialize challenge
-        challenge.participant = ctx.accounts.participant.key();
-        challenge.deposit_amount = deposit_amount;
-        challenge.total_sessions = total_sessions;
-        challenge.completed_sessions = 0;
-        challenge.start_time = clock.unix_timestamp;
-        challenge.end_time = clock.unix_timestamp + (duration_days as i64 * 86400);
-        challenge.status = ChallengeStatus::Active;
-        challenge.verifier = verifier;
-        challenge.challenge_id = config.total_challenges;
-        challenge.last_session_time = 0;
-        challenge.challenge_type = challenge_type;
-        challenge.minimum_interval_hours = calculate_minimum_interval(total_sessions, duration_days);
-        challenge.grace_periods_used = 0;
-        challenge.max_grace_periods = 3; // Allow 3 grace periods per challenge
-        
-        // Update global stats
-        config.total_challenges += 1;
-        config.total_volume += deposit_amount;
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        math::increment(&mut stats_shard.challenges_created)?;
+        stats_shard.volume = math::checked_add(stats_shard.volume, deposit_amount)?;
         
         // Transfer tokens to vault
         let cpi_accounts = TransferChecked {
@@ -113,11 +144,92 @@ pub mod disciplinator {
         
         emit!(ChallengeCreated {
             participant: challenge.participant,
+            challenge: challenge.key(),
             challenge_id: challenge.challenge_id,
             deposit_amount,
             total_sessions,
+            start_time: challenge.start_time,
+            end_time: challenge.end_time,
+            challenge_type_id,
+        });
+        
+        Ok(())
+    }
+
+    pub fn create_challenge_from_template(
+        ctx: Context<CreateChallengeFromTemplate>,
+        deposit_amount: u64,
+        verifier: Option<Pubkey>,
+        start_time: Option<i64>,
+        milestone_step_bps: Option<u16>,
+    ) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        let clock = Clock::get()?;
+        
+        require!(template.active, ErrorCode::TemplateInactive);
+        require!(deposit_amount >= template.min_deposit, ErrorCode::DepositTooSmall);
+        require!(deposit_amount <= template.max_deposit, ErrorCode::DepositTooLarge);
+        // A template with a verifier set only accepts verifiers from it
+        require!(
+            template.verifiers.is_empty() || verifier.is_some_and(|verifier| template.verifiers.contains(&verifier)),
+            ErrorCode::VerifierNotInTemplate
+        );
+        
+        open_challenge(
+            &mut ctx.accounts.challenge,
+            &mut ctx.accounts.user_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.challenge_type,
+            ctx.accounts.participant.key(),
+            ChallengeTerms {
+                deposit_amount,
+                total_sessions: template.total_sessions,
+                duration_days: template.duration_days,
+                verifier,
+                challenge_type_id: template.challenge_type_id,
+                session_goal: template.session_goal.clone(),
+                schedule: template.schedule,
+                grace_terms: template.grace_terms,
+                grace_refund_penalty_bps: template.grace_refund_penalty_bps,
+                start_time,
+                phase_targets: (!template.phase_targets.is_empty()).then(|| template.phase_targets.clone()),
+                milestone_step_bps,
+            },
+            clock.unix_timestamp,
+        )?;
+        let challenge = &mut ctx.accounts.challenge;
+        challenge.template = Some(template.key());
+        math::increment(&mut template.times_used)?;
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        math::increment(&mut stats_shard.challenges_created)?;
+        stats_shard.volume = math::checked_add(stats_shard.volume, deposit_amount)?;
+        
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.participant_token_account.to_account_info(),
+            mint: ctx.accounts.accepted_mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.participant.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_2022::transfer_checked(cpi_ctx, deposit_amount, ctx.accounts.accepted_mint.decimals)?;
+        
+        emit!(ChallengeCreated {
+            participant: challenge.participant,
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            deposit_amount,
+            total_sessions: challenge.total_sessions,
+            start_time: challenge.start_time,
             end_time: challenge.end_time,
-            challenge_type: challenge.challenge_type.clone(),
+            challenge_type_id: challenge.challenge_type_id,
+        });
+        emit!(TemplateUsed {
+            template: template.key(),
+            participant: challenge.participant,
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            times_used: template.times_used,
         });
         
         Ok(())
@@ -125,75 +237,261 @@ pub mod disciplinator {
 
     pub fn mark_session_complete(
         ctx: Context<MarkSession>,
-        proof_ipfs_hash: String,
+        proof: ProofInput,
         session_metadata: SessionMetadata,
     ) -> Result<()> {
-        let challenge_key = ctx.accounts.challenge.key();
-        let challenge = &mut ctx.accounts.challenge;
         let clock = Clock::get()?;
         
-        // Validate challenge status
-        require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
-        require!(clock.unix_timestamp < challenge.end_time, ErrorCode::ChallengeExpired);
-        require!(challenge.completed_sessions < challenge.total_sessions, ErrorCode::AllSessionsCompleted);
-        
         // Verify authorization - only verifier can mark sessions complete
         // This prevents participants from self-verifying and gaming the system
         require!(
-            challenge.verifier.is_some(), 
+            ctx.accounts.challenge.verifier.is_some(), 
             ErrorCode::NoVerifierSet
         );
         require!(
-            challenge.verifier.map_or(false, |v| ctx.accounts.signer.key() == v),
+            ctx.accounts.challenge.verifier == Some(ctx.accounts.signer.key()),
             ErrorCode::UnauthorizedVerifier
         );
         
-        // Validate IPFS hash format
-        validate_ipfs_hash(&proof_ipfs_hash)?;
+        record_session(
+            &mut ctx.accounts.challenge,
+            &mut ctx.accounts.session,
+            &mut ctx.accounts.user_stats,
+            ctx.accounts.signer.key(),
+            proof,
+            session_metadata,
+            clock.unix_timestamp,
+        )?;
+        ctx.accounts.session.rent_payer = ctx.accounts.signer.key();
+        math::increment(&mut ctx.accounts.stats_shard.sessions_completed)?;
         
-        // Check minimum interval between sessions
-        if challenge.last_session_time > 0 {
-            let hours_passed = (clock.unix_timestamp - challenge.last_session_time) / 3600;
-            require!(
-                hours_passed >= challenge.minimum_interval_hours as i64,
-                ErrorCode::SessionTooSoon
+        Ok(())
+    }
+
+    pub fn mark_session_attested(
+        ctx: Context<MarkSessionAttested>,
+        session_number: u32,
+        proof: ProofInput,
+        session_metadata: SessionMetadata,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let attester = ctx.accounts.attester_record.attester;
+        
+        // The session number is part of the signed message, so an attestation
+        // can only ever be applied to the next session of this challenge
+        require!(
+            ctx.accounts.challenge.sessions_recorded.checked_add(1) == Some(session_number),
+            ErrorCode::InvalidAttestation
+        );
+        
+        let message = AttestationMessage {
+            challenge: ctx.accounts.challenge.key(),
+            session_number,
+            proof: proof.clone(),
+            metadata: session_metadata.clone(),
+        }
+        .to_signed_bytes()?;
+        verify_ed25519_attestation(
+            &ctx.accounts.instructions_sysvar.to_account_info(),
+            &attester,
+            &message,
+        )?;
+        
+        record_session(
+            &mut ctx.accounts.challenge,
+            &mut ctx.accounts.session,
+            &mut ctx.accounts.user_stats,
+            attester,
+            proof,
+            session_metadata,
+            clock.unix_timestamp,
+        )?;
+        ctx.accounts.session.rent_payer = ctx.accounts.payer.key();
+        math::increment(&mut ctx.accounts.stats_shard.sessions_completed)?;
+        
+        let attester_record = &mut ctx.accounts.attester_record;
+        math::increment(&mut attester_record.sessions_attested)?;
+        
+        Ok(())
+    }
+
+    pub fn mark_sessions_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MarkSessionsBatch<'info>>,
+        entries: Vec<SessionBatchEntry>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let verifier = ctx.accounts.signer.key();
+        
+        // Each entry is backed by [challenge, session, user_stats, stats_shard] remaining accounts
+        require!(
+            !entries.is_empty() && entries.len() <= MAX_SESSION_BATCH,
+            ErrorCode::InvalidSessionBatch
+        );
+        require!(
+            ctx.remaining_accounts.len() == entries.len() * 4,
+            ErrorCode::InvalidSessionBatch
+        );
+        
+        for (entry, accounts) in entries.into_iter().zip(ctx.remaining_accounts.chunks(4)) {
+            let (challenge_info, session_info, user_stats_info, stats_shard_info) =
+                (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+            require_keys_eq!(challenge_info.key(), entry.challenge, ErrorCode::InvalidSessionBatch);
+            
+            let mut challenge = Account::<Challenge>::try_from(challenge_info)?;
+            require!(challenge.verifier.is_some(), ErrorCode::NoVerifierSet);
+            require!(challenge.verifier == Some(verifier), ErrorCode::UnauthorizedVerifier);
+            
+            // Stats are derived from the challenge's own participant, never a caller-supplied key
+            let (user_stats_key, _) = Pubkey::find_program_address(
+                &[b"user_stats", challenge.participant.as_ref()],
+                &crate::ID,
+            );
+            require_keys_eq!(user_stats_info.key(), user_stats_key, ErrorCode::InvalidSessionBatch);
+            let mut user_stats = Account::<UserStats>::try_from(user_stats_info)?;
+            
+            let (stats_shard_key, _) = Pubkey::find_program_address(
+                &[b"stats_shard", &[stats_shard_index(&challenge.participant)]],
+                &crate::ID,
+            );
+            require_keys_eq!(stats_shard_info.key(), stats_shard_key, ErrorCode::InvalidSessionBatch);
+            let mut stats_shard = Account::<StatsShard>::try_from(stats_shard_info)?;
+            
+            // Same seeds as the `init` constraint in MarkSession
+            let session_index = challenge.sessions_recorded.to_le_bytes();
+            let (session_key, session_bump) = Pubkey::find_program_address(
+                &[b"session", entry.challenge.as_ref(), &session_index],
+                &crate::ID,
             );
+            require_keys_eq!(session_info.key(), session_key, ErrorCode::InvalidSessionBatch);
+            create_pda_account(
+                &ctx.accounts.signer.to_account_info(),
+                session_info,
+                &ctx.accounts.system_program.to_account_info(),
+                8 + Session::INIT_SPACE,
+                &[b"session", entry.challenge.as_ref(), &session_index, &[session_bump]],
+            )?;
+            session_info.try_borrow_mut_data()?[..8].copy_from_slice(Session::DISCRIMINATOR);
+            let mut session = Account::<Session>::try_from(session_info)?;
+            
+            record_session(
+                &mut challenge,
+                &mut session,
+                &mut user_stats,
+                verifier,
+                entry.proof,
+                entry.metadata,
+                clock.unix_timestamp,
+            )?;
+            session.rent_payer = verifier;
+            math::increment(&mut stats_shard.sessions_completed)?;
+            
+            // Persist before the next entry, which may reference the same accounts
+            challenge.exit(&crate::ID)?;
+            session.exit(&crate::ID)?;
+            user_stats.exit(&crate::ID)?;
+            stats_shard.exit(&crate::ID)?;
+        }
+        
+        Ok(())
+    }
+
+    pub fn mark_session_compressed(
+        ctx: Context<MarkSessionCompressed>,
+        proof: ProofInput,
+        session_metadata: SessionMetadata,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let challenge_key = ctx.accounts.challenge.key();
+        let signer = ctx.accounts.signer.key();
+        
+        require!(ctx.accounts.challenge.verifier.is_some(), ErrorCode::NoVerifierSet);
+        require!(
+            ctx.accounts.challenge.verifier == Some(signer),
+            ErrorCode::UnauthorizedVerifier
+        );
+        
+        let proof = register_session(
+            &mut ctx.accounts.challenge,
+            &mut ctx.accounts.user_stats,
+            signer,
+            proof,
+            &session_metadata,
+            clock.unix_timestamp,
+        )?;
+        
+        let session_tree = &mut ctx.accounts.session_tree;
+        if session_tree.challenge == Pubkey::default() {
+            session_tree.challenge = challenge_key;
+            session_tree.rent_payer = signer;
         }
         
-        // Validate session metadata based on challenge type
-        validate_session_metadata(&challenge.challenge_type, &session_metadata)?;
+        // The leaf commits to everything a Session account would have held
+        let challenge = &mut ctx.accounts.challenge;
+        let leaf = SessionLeaf {
+            challenge: challenge_key,
+            session_number: challenge.sessions_recorded,
+            timestamp: clock.unix_timestamp,
+            proof,
+            verified_by: signer,
+            metadata: session_metadata,
+        };
+        let leaf_index = challenge.compressed_sessions;
+        challenge.session_root = merkle::append(
+            &mut session_tree.frontier,
+            leaf_index,
+            merkle::hash_leaf(&leaf.try_to_vec()?),
+        )?;
+        math::increment(&mut challenge.compressed_sessions)?;
+        math::increment(&mut ctx.accounts.stats_shard.sessions_completed)?;
         
-        // Update challenge
-        challenge.completed_sessions += 1;
-        challenge.last_session_time = clock.unix_timestamp;
+        emit!(SessionAppended {
+            leaf,
+            leaf_index,
+            root: challenge.session_root,
+        });
         
-        // Store session record
+        Ok(())
+    }
+
+    pub fn revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
         let session = &mut ctx.accounts.session;
-        session.challenge = challenge_key;
-        session.session_number = challenge.completed_sessions;
-        session.timestamp = clock.unix_timestamp;
-        session.proof_ipfs_hash = proof_ipfs_hash;
-        session.verified_by = ctx.accounts.signer.key();
-        session.metadata = session_metadata;
-        session.auto_verified = false; // Always false since only verifiers can mark sessions
+        let signer = ctx.accounts.signer.key();
+        
+        // Only sessions of a challenge that has not been finalized can be revoked
+        require!(!challenge.is_settled(), ErrorCode::ChallengeNotActive);
+        require!(!session.revoked, ErrorCode::SessionAlreadyRevoked);
+        require!(
+            signer == session.verified_by || signer == ctx.accounts.config.arbiter,
+            ErrorCode::Unauthorized
+        );
+        
+        session.revoked = true;
+        challenge.completed_sessions = challenge.completed_sessions
+            .checked_sub(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        // A revoked session must not keep blocking the next one under the schedule
+        schedule::remove_recent(&mut challenge.recent_session_times, session.timestamp);
+        challenge.last_session_time = challenge.recent_session_times[0];
+        // Phases already settled keep their outcome
+        if session.phase_index == challenge.phase_index {
+            challenge.phase_completed = challenge.phase_completed.saturating_sub(1);
+        }
         
-        // Update user stats
         let user_stats = &mut ctx.accounts.user_stats;
-        user_stats.total_sessions_completed += 1;
-        user_stats.last_activity = clock.unix_timestamp;
+        user_stats.total_sessions_completed = user_stats.total_sessions_completed.saturating_sub(1);
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.sessions_completed = stats_shard.sessions_completed.saturating_sub(1);
         
-        emit!(SessionCompleted {
+        emit!(SessionRevoked {
+            challenge: challenge.key(),
             challenge_id: challenge.challenge_id,
-            session_number: challenge.completed_sessions,
-            timestamp: clock.unix_timestamp,
-            verified_by: ctx.accounts.signer.key(),
+            session_number: session.session_number,
+            revoked_by: signer,
+            timestamp: Clock::get()?.unix_timestamp,
         });
         
-        // Auto-finalize if all sessions completed
-        if challenge.completed_sessions == challenge.total_sessions {
-            msg!("All sessions completed, auto-finalizing challenge");
-        }
-        
         Ok(())
     }
 
@@ -213,25 +511,61 @@ pub mod disciplinator {
             challenge.completed_sessions == challenge.total_sessions,
             ErrorCode::CannotFinalizeYet
         );
+        // Anyone may finalize an ended challenge so the deposit and pledges
+        // can't be held hostage; finishing early is the participant's call
+        require!(
+            clock.unix_timestamp >= challenge.end_time || ctx.accounts.caller.key() == challenge.participant,
+            ErrorCode::Unauthorized
+        );
+        // Earlier phases settle through settle_phase; finalization covers the last one
+        require!(challenge.is_last_phase(), ErrorCode::PhaseSettlementDue);
         
         // Calculate completion and amounts using safe integer arithmetic
-        let refund_amount = challenge.deposit_amount
-            .checked_mul(challenge.completed_sessions as u64)
-            .and_then(|x| x.checked_div(challenge.total_sessions as u64))
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
-        let penalty_amount = challenge.deposit_amount.checked_sub(refund_amount)
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        let (phase_stake, already_refunded) = challenge.last_phase_stake()?;
+        let phase_target = challenge.phase_target();
+        let earned_refund = math::mul_div(
+            phase_stake,
+            challenge.phase_completed.min(phase_target) as u64,
+            phase_target as u64,
+        )?;
+        let earned_refund = math::mul_div(earned_refund, challenge.grace_refund_factor_bps(), math::BASIS_POINTS)?;
+        // Only the remainder is settled now; milestone refunds are never clawed back
+        let refund_amount = earned_refund.saturating_sub(already_refunded);
+        let penalty_amount = math::checked_sub(math::checked_sub(phase_stake, already_refunded)?, refund_amount)?;
+        
+        // Scheduled windows that passed without a session, for reporting only.
+        // Paused time is cut from the end of the range rather than from where
+        // the pauses fell, so on weekday schedules the count is approximate.
+        let missed_windows = challenge.schedule.missed_windows(
+            challenge.start_time,
+            math::sub_seconds(clock.unix_timestamp.min(challenge.end_time), challenge.total_paused_seconds)?,
+            challenge.completed_sessions,
+        )?;
         
         // Store completion rate as percentage (0-10000 for 0.00%-100.00%)
-        let completion_rate_percentage = (challenge.completed_sessions as u64 * 10000) / challenge.total_sessions as u64;
+        let completion_rate_percentage = math::basis_points(challenge.completed_sessions, challenge.total_sessions)?;
+        
+        // Update challenge status (using percentage: 10000 = 100%, 8000 = 80%)
+        let status = if completion_rate_percentage >= math::BASIS_POINTS {
+            ChallengeStatus::Completed
+        } else if completion_rate_percentage >= 8000 {
+            ChallengeStatus::PartiallyCompleted
+        } else {
+            ChallengeStatus::Failed
+        };
+        // Pledges follow the outcome: the participant gets them on success,
+        // otherwise they are forfeited like a penalty
+        let pledges_backed = matches!(status, ChallengeStatus::Completed | ChallengeStatus::PartiallyCompleted);
+        let forfeited_pledges = if pledges_backed { 0 } else { challenge.pledged_total };
+        let total_penalty = math::checked_add(penalty_amount, forfeited_pledges)?;
         
         // Calculate distribution
-        let protocol_fee = (penalty_amount * config.fee_percentage as u64) / 100;
-        let reward_pool_amount = (penalty_amount * config.reward_percentage as u64) / 100;
-        let _charity_amount = penalty_amount - protocol_fee - reward_pool_amount;
+        let protocol_fee = split_penalty(config, total_penalty)?;
         
-        // Transfer refund to participant
-        if refund_amount > 0 {
+        // A recurring challenge keeps its refund in the vault to fund the next run
+        if challenge.recurring {
+            challenge.rollover_amount = refund_amount;
+        } else if refund_amount > 0 {
             transfer_from_vault(
                 &ctx.accounts.vault.to_account_info(),
                 &ctx.accounts.participant_token_account.to_account_info(),
@@ -264,506 +598,3256 @@ pub mod disciplinator {
         
         // Keep rewards and charity in vault for later distribution
         
-        // Update challenge status (using percentage: 10000 = 100%, 8000 = 80%)
-        challenge.status = if completion_rate_percentage >= 10000 {
-            ChallengeStatus::Completed
-        } else if completion_rate_percentage >= 8000 {
-            ChallengeStatus::PartiallyCompleted
-        } else {
-            ChallengeStatus::Failed
-        };
+        challenge.status = status;
+        challenge.finalized_at = clock.unix_timestamp;
+        challenge.refunded_so_far = math::checked_add(challenge.refunded_so_far, refund_amount)?;
+        challenge.penalized_so_far = math::checked_add(challenge.penalized_so_far, total_penalty)?;
         
         // Update user stats
         let user_stats = &mut ctx.accounts.user_stats;
-        user_stats.total_challenges += 1;
-        user_stats.total_deposited += challenge.deposit_amount;
-        user_stats.total_refunded += refund_amount;
-        user_stats.total_penalties += penalty_amount;
+        math::increment(&mut user_stats.total_challenges)?;
+        user_stats.total_deposited = math::checked_add(user_stats.total_deposited, challenge.deposit_amount)?;
+        user_stats.total_refunded = math::checked_add(user_stats.total_refunded, refund_amount)?;
+        user_stats.total_penalties = math::checked_add(user_stats.total_penalties, penalty_amount)?;
         
         match challenge.status {
             ChallengeStatus::Completed => {
-                user_stats.challenges_completed += 1;
-                user_stats.current_streak += 1;
-                user_stats.perfect_completions += 1;
+                math::increment(&mut user_stats.challenges_completed)?;
+                math::increment(&mut user_stats.current_streak)?;
+                math::increment(&mut user_stats.perfect_completions)?;
                 if user_stats.current_streak > user_stats.best_streak {
                     user_stats.best_streak = user_stats.current_streak;
                 }
             },
             ChallengeStatus::PartiallyCompleted => {
-                user_stats.challenges_partial += 1;
+                math::increment(&mut user_stats.challenges_partial)?;
                 // Maintain streak for 80%+ completion
-                user_stats.current_streak += 1;
+                math::increment(&mut user_stats.current_streak)?;
             },
             ChallengeStatus::Failed => {
-                user_stats.challenges_failed += 1;
+                math::increment(&mut user_stats.challenges_failed)?;
                 user_stats.current_streak = 0;
             },
             _ => {}
         }
         
+        if challenge.pledged_total > 0 {
+            if pledges_backed {
+                transfer_from_vault(
+                    &ctx.accounts.vault.to_account_info(),
+                    &ctx.accounts.participant_token_account.to_account_info(),
+                    &ctx.accounts.token_program.to_account_info(),
+                    &ctx.accounts.accepted_mint,
+                    challenge.pledged_total,
+                    &[
+                        b"vault",
+                        config.key().as_ref(),
+                        &[ctx.bumps.vault],
+                    ],
+                )?;
+            }
+            emit!(PledgesSettled {
+                challenge: challenge.key(),
+                challenge_id: challenge.challenge_id,
+                participant: challenge.participant,
+                total: challenge.pledged_total,
+                pledge_count: challenge.pledge_count,
+                paid_to_participant: pledges_backed,
+            });
+        }
+        
+        // Challenges opened from a template report back how the run went
+        if challenge.template.is_some() {
+            let template = ctx.accounts.template.as_mut().ok_or(ErrorCode::TemplateMismatch)?;
+            if challenge.status == ChallengeStatus::Completed {
+                math::increment(&mut template.runs_completed)?;
+            }
+        }
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.refunds = math::checked_add(stats_shard.refunds, refund_amount)?;
+        stats_shard.penalties = math::checked_add(stats_shard.penalties, total_penalty)?;
+        stats_shard.fees = math::checked_add(stats_shard.fees, protocol_fee)?;
+        
         // Record finalization for rewards
         let finalization = &mut ctx.accounts.finalization_record;
         finalization.challenge = challenge_key;
         finalization.participant = challenge.participant;
         finalization.completion_rate_percentage = completion_rate_percentage;
-        finalization.penalty_amount = penalty_amount;
-        finalization.reward_pool_contribution = reward_pool_amount;
+        finalization.penalty_amount = challenge.penalized_so_far;
+        finalization.reward_pool_contribution = math::percentage_of(challenge.penalized_so_far, config.reward_percentage)?;
         finalization.timestamp = clock.unix_timestamp;
         finalization.rewarded = false;
+        finalization.rent_payer = ctx.accounts.caller.key();
         
         emit!(ChallengeFinalized {
+            challenge: challenge.key(),
             challenge_id: challenge.challenge_id,
             participant: challenge.participant,
-            refund_amount,
-            penalty_amount,
+            refund_amount: challenge.refunded_so_far,
+            penalty_amount: challenge.penalized_so_far,
             completion_rate_percentage,
             status: challenge.status.clone(),
+            missed_windows,
         });
         
         Ok(())
     }
 
-    pub fn use_grace_period(ctx: Context<UseGracePeriod>, reason: String) -> Result<()> {
+    pub fn settle_phase(ctx: Context<SettlePhase>) -> Result<()> {
         let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
         let clock = Clock::get()?;
         
         require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
-        require!(challenge.grace_periods_used < challenge.max_grace_periods, ErrorCode::NoGracePeriodsLeft);
-        require!(clock.unix_timestamp < challenge.end_time, ErrorCode::ChallengeExpired);
+        require!(!challenge.is_last_phase(), ErrorCode::NoPhaseToSettle);
+        let phase_target = challenge.phase_target();
+        require!(
+            clock.unix_timestamp >= challenge.phase_end()? || challenge.phase_completed >= phase_target,
+            ErrorCode::PhaseNotOver
+        );
+        
+        let counted = challenge.phase_completed.min(phase_target);
+        let (refund_amount, penalty_amount) = challenge.phase_settlement()?;
+        let protocol_fee = split_penalty(config, penalty_amount)?;
         
-        // Extend challenge by 3 days with overflow protection
-        challenge.end_time = challenge.end_time
-            .checked_add(3 * 86400)
-            .ok_or(ErrorCode::TimeOverflow)?;
-        challenge.grace_periods_used += 1;
+        if refund_amount > 0 {
+            transfer_from_vault(
+                &ctx.accounts.vault.to_account_info(),
+                &ctx.accounts.participant_token_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.accepted_mint,
+                refund_amount,
+                &[
+                    b"vault",
+                    config.key().as_ref(),
+                    &[ctx.bumps.vault],
+                ],
+            )?;
+        }
+        if protocol_fee > 0 {
+            transfer_from_vault(
+                &ctx.accounts.vault.to_account_info(),
+                &ctx.accounts.treasury_token_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.accepted_mint,
+                protocol_fee,
+                &[
+                    b"vault",
+                    config.key().as_ref(),
+                    &[ctx.bumps.vault],
+                ],
+            )?;
+        }
         
-        // Record grace period usage
-        let grace_record = &mut ctx.accounts.grace_record;
-        let challenge_key = challenge.key();
-        grace_record.challenge = challenge_key;
-        grace_record.used_at = clock.unix_timestamp;
-        grace_record.reason = reason;
-        grace_record.new_end_time = challenge.end_time;
+        let settled_phase = challenge.phase_index;
+        challenge.refunded_so_far = math::checked_add(challenge.refunded_so_far, refund_amount)?;
+        challenge.penalized_so_far = math::checked_add(challenge.penalized_so_far, penalty_amount)?;
+        // Sessions beyond the target count toward the next phase
+        challenge.phase_completed = challenge
+            .phase_completed
+            .checked_sub(counted)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        math::increment(&mut challenge.phase_index)?;
         
-        emit!(GracePeriodUsed {
+        let user_stats = &mut ctx.accounts.user_stats;
+        user_stats.total_refunded = math::checked_add(user_stats.total_refunded, refund_amount)?;
+        user_stats.total_penalties = math::checked_add(user_stats.total_penalties, penalty_amount)?;
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.refunds = math::checked_add(stats_shard.refunds, refund_amount)?;
+        stats_shard.penalties = math::checked_add(stats_shard.penalties, penalty_amount)?;
+        stats_shard.fees = math::checked_add(stats_shard.fees, protocol_fee)?;
+        
+        emit!(PhaseSettled {
+            challenge: challenge.key(),
             challenge_id: challenge.challenge_id,
-            grace_periods_remaining: challenge.max_grace_periods - challenge.grace_periods_used,
-            new_end_time: challenge.end_time,
+            phase_index: settled_phase,
+            sessions_counted: counted,
+            session_target: phase_target,
+            refund_amount,
+            penalty_amount,
         });
         
         Ok(())
     }
 
-    pub fn distribute_rewards(ctx: Context<DistributeRewards>, epoch: u64) -> Result<()> {
-        let clock = Clock::get()?;
-        let reward_state = &mut ctx.accounts.reward_state;
+    pub fn claim_milestone_refund(ctx: Context<ClaimMilestoneRefund>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
         
-        // Ensure epoch hasn't been processed
-        require!(reward_state.last_epoch_processed < epoch, ErrorCode::EpochAlreadyProcessed);
-        require!(clock.unix_timestamp >= reward_state.next_epoch_time, ErrorCode::EpochNotReady);
+        require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+        require!(challenge.milestone_step_bps > 0, ErrorCode::MilestonesDisabled);
         
-        // Calculate total rewards to distribute
-        let vault_balance = ctx.accounts.vault.amount;
-        let reserved_amount = ctx.accounts.vault_reserve.amount;
-        let available_rewards = vault_balance.saturating_sub(reserved_amount);
+        // Based on current sessions, so revoked sessions lock their milestone again
+        let claimable = challenge.milestone_refund_unlocked()?.saturating_sub(challenge.refunded_so_far);
+        require!(claimable > 0, ErrorCode::NoMilestoneRefund);
         
-        // Update reward state
-        reward_state.last_epoch_processed = epoch;
-        reward_state.next_epoch_time = clock.unix_timestamp + (7 * 86400); // Weekly
-        reward_state.total_distributed += available_rewards;
+        transfer_from_vault(
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.participant_token_account.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.accepted_mint,
+            claimable,
+            &[
+                b"vault",
+                config.key().as_ref(),
+                &[ctx.bumps.vault],
+            ],
+        )?;
+        challenge.refunded_so_far = math::checked_add(challenge.refunded_so_far, claimable)?;
         
-        emit!(RewardsDistributed {
-            epoch,
-            amount: available_rewards,
-            timestamp: clock.unix_timestamp,
+        let user_stats = &mut ctx.accounts.user_stats;
+        user_stats.total_refunded = math::checked_add(user_stats.total_refunded, claimable)?;
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.refunds = math::checked_add(stats_shard.refunds, claimable)?;
+        
+        emit!(MilestoneRefundClaimed {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            amount: claimable,
+            refunded_so_far: challenge.refunded_so_far,
         });
         
         Ok(())
     }
 
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
-        let user_stats = &ctx.accounts.user_stats;
-        let reward_state = &ctx.accounts.reward_state;
-        let _clock = Clock::get()?;
+    pub fn use_grace_period(ctx: Context<UseGracePeriod>, reason: String) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let clock = Clock::get()?;
         
-        // Check eligibility
-        require!(
-            user_stats.perfect_completions > 0,
-            ErrorCode::NotEligibleForRewards
-        );
+        require!(challenge.grace_periods_used < challenge.max_grace_periods, ErrorCode::NoGracePeriodsLeft);
+        apply_grace_period(challenge, &mut ctx.accounts.grace_record, reason, clock.unix_timestamp)?;
+        
+        emit!(GracePeriodUsed {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            grace_periods_remaining: challenge.max_grace_periods - challenge.grace_periods_used,
+            new_end_time: challenge.end_time,
+            extension_days: challenge.grace_period_days,
+        });
+        
+        Ok(())
+    }
+
+    pub fn buy_grace_period(ctx: Context<BuyGracePeriod>, reason: String) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
+        let clock = Clock::get()?;
+        
+        // Purchases only start once the free quota is spent
+        require!(challenge.grace_periods_used >= challenge.max_grace_periods, ErrorCode::GracePeriodsStillAvailable);
         require!(
-            user_stats.last_claim_epoch < reward_state.last_epoch_processed,
-            ErrorCode::AlreadyClaimedThisEpoch
+            challenge.grace_periods_purchased < config.max_grace_purchases,
+            ErrorCode::NoGracePurchasesLeft
         );
+        let price = grace_purchase_price(config, challenge.grace_periods_purchased)?;
         
-        // Calculate reward amount based on performance score
-        let performance_score = calculate_performance_score(user_stats);
-        let epoch_records = &ctx.remaining_accounts; // Finalization records for the epoch
-        let total_epoch_score = calculate_total_epoch_score(epoch_records)?;
+        apply_grace_period(challenge, &mut ctx.accounts.grace_record, reason, clock.unix_timestamp)?;
+        math::increment(&mut challenge.grace_periods_purchased)?;
         
-        // Calculate reward amount using safe integer arithmetic
-        let reward_amount = if total_epoch_score > 0 {
-            ctx.accounts.vault_rewards.amount
-                .checked_mul(performance_score)
-                .and_then(|x| x.checked_div(total_epoch_score))
-                .unwrap_or(0)
-        } else {
-            0
+        // The fee goes straight into the reward pool
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.participant_token_account.to_account_info(),
+            mint: ctx.accounts.accepted_mint.to_account_info(),
+            to: ctx.accounts.vault_rewards.to_account_info(),
+            authority: ctx.accounts.participant.to_account_info(),
         };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_2022::transfer_checked(cpi_ctx, price, ctx.accounts.accepted_mint.decimals)?;
         
-        // Verify sufficient funds before transfer
-        require!(
-            ctx.accounts.vault_rewards.amount >= reward_amount,
-            ErrorCode::InsufficientRewards
-        );
+        emit!(GracePeriodPurchased {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            price,
+            grace_periods_purchased: challenge.grace_periods_purchased,
+            new_end_time: challenge.end_time,
+            extension_days: challenge.grace_period_days,
+        });
         
-        // Transfer rewards
-        if reward_amount > 0 {
+        Ok(())
+    }
+
+    pub fn set_recurring(ctx: Context<SetRecurring>, recurring: bool) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        
+        // After finalization, opting out goes through release_rollover
+        require!(!challenge.is_settled(), ErrorCode::ChallengeNotActive);
+        challenge.recurring = recurring;
+        
+        emit!(RecurringUpdated {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            recurring,
+        });
+        
+        Ok(())
+    }
+
+    pub fn renew_challenge(ctx: Context<RenewChallenge>) -> Result<()> {
+        let previous = &mut ctx.accounts.previous_challenge;
+        let config = &ctx.accounts.config;
+        let clock = Clock::get()?;
+        
+        require!(!config.paused, ErrorCode::ProtocolPaused);
+        require!(previous.recurring && previous.is_settled(), ErrorCode::NotRenewable);
+        validate_stake(config, &ctx.accounts.challenge_type, previous.deposit_amount)?;
+        
+        // The rolled-over refund covers what it can; the delegate allowance pays the rest
+        let shortfall = previous.deposit_amount.saturating_sub(previous.rollover_amount);
+        if shortfall > 0 {
+            require!(
+                renewal_allowance_covers(
+                    &ctx.accounts.participant_token_account,
+                    &ctx.accounts.renewal_authority.key(),
+                    shortfall,
+                ),
+                ErrorCode::RenewalAllowanceExhausted
+            );
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.participant_token_account.to_account_info(),
+                mint: ctx.accounts.accepted_mint.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+                authority: ctx.accounts.renewal_authority.to_account_info(),
+            };
+            let config_key = config.key();
+            let seeds: &[&[u8]] = &[b"renewal_authority", config_key.as_ref(), &[ctx.bumps.renewal_authority]];
+            let signer_seeds = &[seeds];
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                signer_seeds,
+            );
+            token_2022::transfer_checked(cpi_ctx, shortfall, ctx.accounts.accepted_mint.decimals)?;
+        }
+        // Anything beyond the deposit goes back to the participant
+        let excess = previous.rollover_amount.saturating_sub(previous.deposit_amount);
+        if excess > 0 {
             transfer_from_vault(
-                &ctx.accounts.vault_rewards.to_account_info(),
+                &ctx.accounts.vault.to_account_info(),
                 &ctx.accounts.participant_token_account.to_account_info(),
                 &ctx.accounts.token_program.to_account_info(),
                 &ctx.accounts.accepted_mint,
-                reward_amount,
+                excess,
                 &[
-                    b"vault_rewards",
-                    ctx.accounts.config.key().as_ref(),
-                    &[ctx.bumps.vault_rewards],
+                    b"vault",
+                    config.key().as_ref(),
+                    &[ctx.bumps.vault],
                 ],
             )?;
         }
         
-        // Update user stats
         let user_stats = &mut ctx.accounts.user_stats;
-        user_stats.total_rewards_claimed += reward_amount;
-        user_stats.last_claim_epoch = reward_state.last_epoch_processed;
+        // The chosen milestone step must still meet the protocol's current minimum
+        validate_milestone_step(config, &previous.phase_targets, previous.milestone_step_bps)?;
+        let next = previous.next_run(user_stats.challenges_created, clock.unix_timestamp)?;
+        math::increment(&mut user_stats.challenges_created)?;
         
-        emit!(RewardsClaimed {
-            participant: ctx.accounts.participant.key(),
-            amount: reward_amount,
-            epoch: reward_state.last_epoch_processed,
-            performance_score,
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        math::increment(&mut stats_shard.challenges_created)?;
+        stats_shard.volume = math::checked_add(stats_shard.volume, next.deposit_amount)?;
+        
+        previous.recurring = false;
+        previous.rollover_amount = 0;
+        
+        emit!(ChallengeRenewed {
+            participant: next.participant,
+            previous_challenge: previous.key(),
+            previous_challenge_id: previous.challenge_id,
+            challenge: ctx.accounts.next_challenge.key(),
+            challenge_id: next.challenge_id,
+            deposit_amount: next.deposit_amount,
+            rolled_over: previous.deposit_amount - shortfall,
+            pulled_from_allowance: shortfall,
+            end_time: next.end_time,
         });
+        ctx.accounts.next_challenge.set_inner(next);
         
         Ok(())
     }
 
-    pub fn pause_protocol(ctx: Context<PauseProtocol>) -> Result<()> {
+    pub fn release_rollover(ctx: Context<ReleaseRollover>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
+        let signer = ctx.accounts.signer.key();
+        
+        require!(challenge.recurring && challenge.is_settled(), ErrorCode::NotRenewable);
+        // The participant may opt out at any time; anyone else only once renewal can't be funded
+        if signer != challenge.participant {
+            let shortfall = challenge.deposit_amount.saturating_sub(challenge.rollover_amount);
+            require!(
+                shortfall > 0
+                    && !renewal_allowance_covers(
+                        &ctx.accounts.participant_token_account,
+                        &ctx.accounts.renewal_authority.key(),
+                        shortfall,
+                    ),
+                ErrorCode::Unauthorized
+            );
+        }
+        
+        let amount = challenge.rollover_amount;
+        if amount > 0 {
+            transfer_from_vault(
+                &ctx.accounts.vault.to_account_info(),
+                &ctx.accounts.participant_token_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.accepted_mint,
+                amount,
+                &[
+                    b"vault",
+                    config.key().as_ref(),
+                    &[ctx.bumps.vault],
+                ],
+            )?;
+        }
+        challenge.recurring = false;
+        challenge.rollover_amount = 0;
+        
+        emit!(RenewalStopped {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            released_amount: amount,
+            stopped_by: signer,
+        });
+        
+        Ok(())
+    }
+
+    pub fn increase_deposit(ctx: Context<IncreaseDeposit>, amount: u64) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
+        let clock = Clock::get()?;
+        
+        require!(!config.paused, ErrorCode::ProtocolPaused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+        require!(clock.unix_timestamp < challenge.end_time, ErrorCode::ChallengeExpired);
+        
+        let new_deposit = math::checked_add(challenge.deposit_amount, amount)?;
+        validate_stake(config, &ctx.accounts.challenge_type, new_deposit)?;
+        // A template may have narrowed the range further
+        if challenge.template.is_some() {
+            let template = ctx.accounts.template.as_ref().ok_or(ErrorCode::TemplateMismatch)?;
+            require!(new_deposit <= template.max_deposit, ErrorCode::DepositTooLarge);
+        }
+        
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.participant_token_account.to_account_info(),
+            mint: ctx.accounts.accepted_mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.participant.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_2022::transfer_checked(cpi_ctx, amount, ctx.accounts.accepted_mint.decimals)?;
+        
+        // UserStats.total_deposited picks up the new amount at finalization
+        challenge.deposit_amount = new_deposit;
+        challenge.exclude_from_passed_milestones(amount)?;
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.volume = math::checked_add(stats_shard.volume, amount)?;
+        
+        emit!(DepositIncreased {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            amount,
+            new_deposit_amount: new_deposit,
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn pledge_to_challenge(ctx: Context<PledgeToChallenge>, amount: u64) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
+        let supporter = ctx.accounts.supporter.key();
+        let clock = Clock::get()?;
+        
+        require!(!config.paused, ErrorCode::ProtocolPaused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!challenge.is_settled(), ErrorCode::ChallengeNotActive);
+        require!(clock.unix_timestamp < challenge.end_time, ErrorCode::ChallengeExpired);
+        // Participants raise their own stake with increase_deposit
+        require!(supporter != challenge.participant, ErrorCode::CannotPledgeOwnChallenge);
+        
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.supporter_token_account.to_account_info(),
+            mint: ctx.accounts.accepted_mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.supporter.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_2022::transfer_checked(cpi_ctx, amount, ctx.accounts.accepted_mint.decimals)?;
+        
+        // Repeat pledges from the same supporter top up one account
+        let pledge = &mut ctx.accounts.pledge;
+        if pledge.amount == 0 {
+            pledge.challenge = challenge.key();
+            pledge.supporter = supporter;
+            pledge.pledged_at = clock.unix_timestamp;
+            math::increment(&mut challenge.pledge_count)?;
+        }
+        pledge.amount = math::checked_add(pledge.amount, amount)?;
+        challenge.pledged_total = math::checked_add(challenge.pledged_total, amount)?;
+        
+        emit!(PledgeMade {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            supporter,
+            amount,
+            pledged_total: challenge.pledged_total,
+        });
+        
+        Ok(())
+    }
+
+    pub fn refund_pledge(ctx: Context<RefundPledge>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
+        let amount = ctx.accounts.pledge.amount;
+        
+        require!(challenge.status == ChallengeStatus::Cancelled, ErrorCode::ChallengeNotCancelled);
+        
+        transfer_from_vault(
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.supporter_token_account.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.accepted_mint,
+            amount,
+            &[
+                b"vault",
+                config.key().as_ref(),
+                &[ctx.bumps.vault],
+            ],
+        )?;
+        challenge.pledged_total = math::checked_sub(challenge.pledged_total, amount)?;
+        
+        emit!(PledgeRefunded {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            supporter: ctx.accounts.supporter.key(),
+            amount,
+        });
+        
+        Ok(())
+    }
+
+    pub fn cancel_challenge(ctx: Context<CancelChallenge>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let config = &ctx.accounts.config;
+        let clock = Clock::get()?;
+        
+        // Only a deferred challenge that hasn't started can be backed out of
+        require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+        require!(clock.unix_timestamp < challenge.start_time, ErrorCode::ChallengeAlreadyStarted);
+        
+        transfer_from_vault(
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.participant_token_account.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.accepted_mint,
+            challenge.deposit_amount,
+            &[
+                b"vault",
+                config.key().as_ref(),
+                &[ctx.bumps.vault],
+            ],
+        )?;
+        
+        challenge.status = ChallengeStatus::Cancelled;
+        challenge.recurring = false;
+        challenge.finalized_at = clock.unix_timestamp;
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.refunds = math::checked_add(stats_shard.refunds, challenge.deposit_amount)?;
+        
+        emit!(ChallengeCancelled {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            refund_amount: challenge.deposit_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_min_milestone_step(ctx: Context<PauseProtocol>, min_milestone_step_bps: u16) -> Result<()> {
+        require!(
+            min_milestone_step_bps == 0
+                || (MIN_MILESTONE_STEP_BPS as u64..=math::BASIS_POINTS).contains(&(min_milestone_step_bps as u64)),
+            ErrorCode::InvalidMilestoneStep
+        );
+        
         let config = &mut ctx.accounts.config;
-        config.paused = true;
+        config.min_milestone_step_bps = min_milestone_step_bps;
         
-        emit!(ProtocolPaused {
+        emit!(MinMilestoneStepUpdated {
             authority: ctx.accounts.authority.key(),
-            timestamp: Clock::get()?.unix_timestamp,
+            min_milestone_step_bps,
         });
         
         Ok(())
     }
 
-    pub fn unpause_protocol(ctx: Context<PauseProtocol>) -> Result<()> {
+    pub fn set_max_start_delay(ctx: Context<PauseProtocol>, max_start_delay_days: u16) -> Result<()> {
         let config = &mut ctx.accounts.config;
-        config.paused = false;
+        config.max_start_delay_days = max_start_delay_days;
         
-        emit!(ProtocolUnpaused {
+        emit!(MaxStartDelayUpdated {
             authority: ctx.accounts.authority.key(),
-            timestamp: Clock::get()?.unix_timestamp,
+            max_start_delay_days,
         });
         
         Ok(())
     }
-}
 
-// Helper functions
-fn validate_ipfs_hash(hash: &str) -> Result<()> {
-    // IPFS hash validation: should be 46 characters and start with "Qm"
-    require!(
-        hash.len() == 46 && hash.starts_with("Qm"),
-        ErrorCode::InvalidIPFSHash
-    );
-    
-    // Additional validation: check if it contains only valid base58 characters
-    let valid_chars = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
-    require!(
-        hash.chars().all(|c| valid_chars.contains(c)),
-        ErrorCode::InvalidIPFSHash
-    );
-    
-    Ok(())
-}
+    pub fn set_max_pause_duration(ctx: Context<PauseProtocol>, max_pause_days: u16) -> Result<()> {
+        require!(max_pause_days > 0, ErrorCode::InvalidPauseDuration);
+        
+        let config = &mut ctx.accounts.config;
+        config.max_pause_days = max_pause_days;
+        
+        emit!(MaxPauseDurationUpdated {
+            authority: ctx.accounts.authority.key(),
+            max_pause_days,
+        });
+        
+        Ok(())
+    }
 
-fn calculate_minimum_interval(total_sessions: u32, duration_days: u32) -> u16 {
-    let total_hours = duration_days as f64 * 24.0;
-    let interval = total_hours / total_sessions as f64;
-    // Minimum 12 hours, maximum 48 hours between sessions
-    (interval.max(12.0).min(48.0)) as u16
-}
+    pub fn pause_challenge(ctx: Context<PauseChallenge>, reason: String) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let clock = Clock::get()?;
+        
+        require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+        require!(clock.unix_timestamp >= challenge.start_time, ErrorCode::ChallengeNotStarted);
+        require!(clock.unix_timestamp < challenge.end_time, ErrorCode::ChallengeExpired);
+        require!(challenge.pause_requested_at == 0, ErrorCode::PauseAlreadyRequested);
+        require!(reason.len() <= MAX_PAUSE_REASON_LEN, ErrorCode::ReasonTooLong);
+        
+        challenge.pause_requested_at = clock.unix_timestamp;
+        
+        // The reason is only emitted, not stored on the challenge
+        emit!(PauseRequested {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            reason,
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
 
-fn validate_session_metadata(
-    challenge_type: &ChallengeType,
-    metadata: &SessionMetadata,
-) -> Result<()> {
-    match challenge_type {
-        ChallengeType::Fitness => {
-            require!(
-                metadata.duration_minutes.unwrap_or(0) >= 20,
-                ErrorCode::InvalidSessionDuration
-            );
-        },
-        ChallengeType::Education => {
-            require!(
-                metadata.duration_minutes.unwrap_or(0) >= 30,
-                ErrorCode::InvalidSessionDuration
-            );
-        },
-        ChallengeType::Meditation => {
-            require!(
-                metadata.duration_minutes.unwrap_or(0) >= 10,
-                ErrorCode::InvalidSessionDuration
-            );
-        },
-        ChallengeType::Custom => {
-            // Custom challenges have flexible requirements
-        },
+    pub fn withdraw_pause_request(ctx: Context<PauseChallenge>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let clock = Clock::get()?;
+        
+        require!(challenge.pause_requested_at != 0, ErrorCode::PauseNotRequested);
+        
+        challenge.pause_requested_at = 0;
+        
+        emit!(PauseRequestWithdrawn {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
     }
-    Ok(())
-}
 
-fn calculate_performance_score(stats: &UserStats) -> u64 {
-    let base_score = stats.perfect_completions as u64 * 100;
-    let streak_bonus = stats.best_streak as u64 * 10;
-    let consistency_bonus = if stats.challenges_failed == 0 { 50 } else { 0 };
-    
-    base_score + streak_bonus + consistency_bonus
-}
+    pub fn approve_pause(ctx: Context<ApprovePause>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let approver = ctx.accounts.approver.key();
+        let clock = Clock::get()?;
+        
+        require!(
+            challenge.verifier == Some(approver) || approver == ctx.accounts.config.arbiter,
+            ErrorCode::Unauthorized
+        );
+        require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+        require!(challenge.pause_requested_at != 0, ErrorCode::PauseNotRequested);
+        // Time left at approval is what the pause preserves
+        require!(clock.unix_timestamp < challenge.end_time, ErrorCode::ChallengeExpired);
+        
+        challenge.status = ChallengeStatus::Paused;
+        challenge.pause_requested_at = 0;
+        challenge.paused_at = clock.unix_timestamp;
+        
+        emit!(ChallengePaused {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            approved_by: approver,
+            paused_at: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
 
-fn calculate_total_epoch_score(_records: &[AccountInfo]) -> Result<u64> {
-    // Sum up all performance scores from finalization records
-    // Implementation depends on how you want to iterate through accounts
+    pub fn resume_challenge(ctx: Context<ResumeChallenge>) -> Result<()> {
+        let challenge = &mut ctx.accounts.challenge;
+        let clock = Clock::get()?;
+        
+        require!(challenge.status == ChallengeStatus::Paused, ErrorCode::ChallengeNotPaused);
+        
+        // Once the pause has run its maximum anyone may resume it, so a
+        // participant cannot hold a challenge open indefinitely
+        let max_pause = math::days_to_seconds(ctx.accounts.config.max_pause_days as u32);
+        let (paused_seconds, expired) = challenge.pause_credit(clock.unix_timestamp, max_pause)?;
+        require!(
+            ctx.accounts.caller.key() == challenge.participant || expired,
+            ErrorCode::Unauthorized
+        );
+        
+        // Paused time does not count toward expiry, up to the maximum
+        challenge.end_time = math::add_seconds(challenge.end_time, paused_seconds)?;
+        challenge.total_paused_seconds = math::add_seconds(challenge.total_paused_seconds, paused_seconds)?;
+        challenge.status = ChallengeStatus::Active;
+        challenge.paused_at = 0;
+        
+        emit!(ChallengeResumed {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            paused_seconds,
+            new_end_time: challenge.end_time,
+        });
+        
+        Ok(())
+    }
+
+    pub fn distribute_rewards(ctx: Context<DistributeRewards>, epoch: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let reward_state = &mut ctx.accounts.reward_state;
+        
+        // Ensure epoch hasn't been processed
+        require!(reward_state.last_epoch_processed < epoch, ErrorCode::EpochAlreadyProcessed);
+        require!(clock.unix_timestamp >= reward_state.next_epoch_time, ErrorCode::EpochNotReady);
+        
+        // Calculate total rewards to distribute
+        let vault_balance = ctx.accounts.vault.amount;
+        let reserved_amount = ctx.accounts.vault_reserve.amount;
+        let available_rewards = vault_balance.saturating_sub(reserved_amount);
+        
+        // Update reward state
+        reward_state.last_epoch_processed = epoch;
+        reward_state.next_epoch_time = math::add_seconds(clock.unix_timestamp, math::days_to_seconds(7))?; // Weekly
+        reward_state.total_distributed = math::checked_add(reward_state.total_distributed, available_rewards)?;
+        reward_state.last_distribution_time = clock.unix_timestamp;
+        
+        emit!(RewardsDistributed {
+            epoch,
+            amount: available_rewards,
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let user_stats = &ctx.accounts.user_stats;
+        let reward_state = &ctx.accounts.reward_state;
+        let _clock = Clock::get()?;
+        
+        // Check eligibility
+        require!(
+            user_stats.perfect_completions > 0,
+            ErrorCode::NotEligibleForRewards
+        );
+        require!(
+            user_stats.last_claim_epoch < reward_state.last_epoch_processed,
+            ErrorCode::AlreadyClaimedThisEpoch
+        );
+        
+        // Calculate reward amount based on performance score
+        let performance_score = calculate_performance_score(user_stats);
+        let epoch_records = &ctx.remaining_accounts; // Finalization records for the epoch
+        let total_epoch_score = calculate_total_epoch_score(epoch_records)?;
+        
+        // Calculate reward amount using safe integer arithmetic
+        let reward_amount = if total_epoch_score > 0 {
+            math::mul_div(ctx.accounts.vault_rewards.amount, performance_score, total_epoch_score)?
+        } else {
+            0
+        };
+        
+        // Verify sufficient funds before transfer
+        require!(
+            ctx.accounts.vault_rewards.amount >= reward_amount,
+            ErrorCode::InsufficientRewards
+        );
+        
+        // Transfer rewards
+        if reward_amount > 0 {
+            transfer_from_vault(
+                &ctx.accounts.vault_rewards.to_account_info(),
+                &ctx.accounts.participant_token_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.accepted_mint,
+                reward_amount,
+                &[
+                    b"vault_rewards",
+                    ctx.accounts.config.key().as_ref(),
+                    &[ctx.bumps.vault_rewards],
+                ],
+            )?;
+        }
+        
+        // Update user stats
+        let user_stats = &mut ctx.accounts.user_stats;
+        user_stats.total_rewards_claimed = math::checked_add(user_stats.total_rewards_claimed, reward_amount)?;
+        user_stats.last_claim_epoch = reward_state.last_epoch_processed;
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.rewards_claimed = math::checked_add(stats_shard.rewards_claimed, reward_amount)?;
+        
+        emit!(RewardsClaimed {
+            participant: ctx.accounts.participant.key(),
+            amount: reward_amount,
+            epoch: reward_state.last_epoch_processed,
+            performance_score,
+        });
+        
+        Ok(())
+    }
+
+    pub fn pause_protocol(ctx: Context<PauseProtocol>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = true;
+        
+        emit!(ProtocolPaused {
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn unpause_protocol(ctx: Context<PauseProtocol>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = false;
+        
+        emit!(ProtocolUnpaused {
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_arbiter(ctx: Context<PauseProtocol>, arbiter: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.arbiter = arbiter;
+        
+        emit!(ArbiterUpdated {
+            authority: ctx.accounts.authority.key(),
+            arbiter,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_grace_pricing(
+        ctx: Context<PauseProtocol>,
+        base_price: u64,
+        price_increment: u64,
+        max_purchases: u8,
+    ) -> Result<()> {
+        // A free purchase would bypass the per-type grace quota
+        require!(max_purchases == 0 || base_price > 0, ErrorCode::InvalidGracePricing);
+        
+        let config = &mut ctx.accounts.config;
+        config.grace_price_base = base_price;
+        config.grace_price_increment = price_increment;
+        config.max_grace_purchases = max_purchases;
+        
+        emit!(GracePricingUpdated {
+            authority: ctx.accounts.authority.key(),
+            base_price,
+            price_increment,
+            max_purchases,
+        });
+        
+        Ok(())
+    }
+
+    pub fn init_stats_shard(ctx: Context<InitStatsShard>, shard_index: u8) -> Result<()> {
+        require!(shard_index < STATS_SHARD_COUNT, ErrorCode::InvalidStatsShard);
+        
+        let stats_shard = &mut ctx.accounts.stats_shard;
+        stats_shard.shard_index = shard_index;
+        
+        Ok(())
+    }
+
+    pub fn create_challenge_type(
+        ctx: Context<CreateChallengeType>,
+        type_id: u16,
+        params: ChallengeTypeParams,
+    ) -> Result<()> {
+        validate_challenge_type_params(&ctx.accounts.config, &params)?;
+        
+        let challenge_type = &mut ctx.accounts.challenge_type;
+        challenge_type.type_id = type_id;
+        challenge_type.apply(params);
+        
+        emit!(ChallengeTypeUpdated {
+            type_id,
+            name: challenge_type.name.clone(),
+            active: challenge_type.active,
+        });
+        
+        Ok(())
+    }
+
+    pub fn update_challenge_type(
+        ctx: Context<UpdateChallengeType>,
+        params: ChallengeTypeParams,
+    ) -> Result<()> {
+        validate_challenge_type_params(&ctx.accounts.config, &params)?;
+        
+        let challenge_type = &mut ctx.accounts.challenge_type;
+        challenge_type.apply(params);
+        
+        emit!(ChallengeTypeUpdated {
+            type_id: challenge_type.type_id,
+            name: challenge_type.name.clone(),
+            active: challenge_type.active,
+        });
+        
+        Ok(())
+    }
+
+    pub fn register_attester(ctx: Context<RegisterAttester>, attester: Pubkey) -> Result<()> {
+        let clock = Clock::get()?;
+        let attester_record = &mut ctx.accounts.attester_record;
+        attester_record.attester = attester;
+        attester_record.registered_at = clock.unix_timestamp;
+        attester_record.sessions_attested = 0;
+        
+        emit!(AttesterRegistered {
+            attester,
+            authority: ctx.accounts.authority.key(),
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn revoke_attester(ctx: Context<RevokeAttester>) -> Result<()> {
+        // Closing the record is enough: attested sessions require it to exist
+        emit!(AttesterRevoked {
+            attester: ctx.accounts.attester_record.attester,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn register_coach(ctx: Context<RegisterCoach>, coach: Pubkey) -> Result<()> {
+        let clock = Clock::get()?;
+        let coach_record = &mut ctx.accounts.coach_record;
+        coach_record.coach = coach;
+        coach_record.registered_at = clock.unix_timestamp;
+        
+        emit!(CoachRegistered {
+            coach,
+            authority: ctx.accounts.authority.key(),
+            timestamp: clock.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn revoke_coach(ctx: Context<RevokeCoach>) -> Result<()> {
+        // Published templates stay usable; the coach just can't publish new ones
+        emit!(CoachRevoked {
+            coach: ctx.accounts.coach_record.coach,
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn create_challenge_template(
+        ctx: Context<CreateChallengeTemplate>,
+        template_id: u64,
+        params: ChallengeTemplateParams,
+    ) -> Result<()> {
+        let creator = ctx.accounts.creator.key();
+        require!(
+            creator == ctx.accounts.config.authority || ctx.accounts.coach_record.is_some(),
+            ErrorCode::Unauthorized
+        );
+        validate_template_params(&ctx.accounts.config, &ctx.accounts.challenge_type, &params)?;
+        
+        let template = &mut ctx.accounts.template;
+        template.creator = creator;
+        template.template_id = template_id;
+        template.name = params.name;
+        template.challenge_type_id = params.challenge_type_id;
+        template.total_sessions = params.total_sessions;
+        template.duration_days = params.duration_days;
+        template.session_goal = params.session_goal;
+        template.schedule = params.schedule;
+        template.grace_terms = params.grace_terms;
+        template.grace_refund_penalty_bps = params.grace_refund_penalty_bps;
+        template.phase_targets = params.phase_targets;
+        template.verifiers = params.verifiers;
+        template.min_deposit = params.min_deposit;
+        template.max_deposit = params.max_deposit;
+        template.times_used = 0;
+        template.runs_completed = 0;
+        template.active = true;
+        
+        emit!(TemplateCreated {
+            template: template.key(),
+            creator,
+            template_id,
+            name: template.name.clone(),
+            challenge_type_id: template.challenge_type_id,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_template_active(ctx: Context<SetTemplateActive>, active: bool) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        template.active = active;
+        
+        emit!(TemplateActiveUpdated {
+            template: template.key(),
+            active,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_session(ctx: Context<CloseSession>) -> Result<()> {
+        require_dispute_window_closed(&ctx.accounts.challenge, Clock::get()?.unix_timestamp)?;
+        
+        // The account is gone after this instruction; indexers keep the event
+        let session = &ctx.accounts.session;
+        emit!(SessionClosed {
+            challenge: session.challenge,
+            session_number: session.session_number,
+            timestamp: session.timestamp,
+            proof: session.proof.clone(),
+            verified_by: session.verified_by,
+            metadata: session.metadata.clone(),
+            revoked: session.revoked,
+            rent_payer: session.rent_payer,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_session_tree(ctx: Context<CloseSessionTree>) -> Result<()> {
+        require_dispute_window_closed(&ctx.accounts.challenge, Clock::get()?.unix_timestamp)?;
+        
+        emit!(SessionTreeClosed {
+            challenge: ctx.accounts.session_tree.challenge,
+            rent_payer: ctx.accounts.session_tree.rent_payer,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_grace_record(ctx: Context<CloseGraceRecord>) -> Result<()> {
+        require_dispute_window_closed(&ctx.accounts.challenge, Clock::get()?.unix_timestamp)?;
+        
+        let grace_record = &ctx.accounts.grace_record;
+        emit!(GracePeriodRecordClosed {
+            challenge: grace_record.challenge,
+            used_at: grace_record.used_at,
+            reason: grace_record.reason.clone(),
+            new_end_time: grace_record.new_end_time,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_pledge(ctx: Context<ClosePledge>) -> Result<()> {
+        // Pledges on a cancelled challenge are closed by refund_pledge instead
+        require!(
+            challenge_pledges_paid_out(&ctx.accounts.challenge)?,
+            ErrorCode::ChallengeNotFinalized
+        );
+        
+        let pledge = &ctx.accounts.pledge;
+        emit!(PledgeClosed {
+            challenge: pledge.challenge,
+            supporter: pledge.supporter,
+            amount: pledge.amount,
+            pledged_at: pledge.pledged_at,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_challenge(ctx: Context<CloseChallenge>) -> Result<()> {
+        let challenge = &ctx.accounts.challenge;
+        let clock = Clock::get()?;
+        
+        require!(challenge.is_settled(), ErrorCode::ChallengeNotFinalized);
+        require!(!challenge.recurring, ErrorCode::RenewalPending);
+        // Supporters of a cancelled challenge need it to claim their refunds
+        require!(
+            challenge.pledged_total == 0 || challenge.status != ChallengeStatus::Cancelled,
+            ErrorCode::PledgesOutstanding
+        );
+        require!(
+            clock.unix_timestamp >= math::add_seconds(challenge.finalized_at, CHALLENGE_DISPUTE_WINDOW)?,
+            ErrorCode::DisputeWindowOpen
+        );
+        
+        // finalize_challenge already recorded the outcome; fold in what only
+        // the challenge account still knows before it disappears
+        let user_stats = &mut ctx.accounts.user_stats;
+        let revoked = challenge
+            .sessions_recorded
+            .checked_sub(challenge.completed_sessions)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        user_stats.total_grace_periods_used = user_stats
+            .total_grace_periods_used
+            .checked_add(challenge.grace_periods_used as u32)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        user_stats.total_sessions_revoked = user_stats
+            .total_sessions_revoked
+            .checked_add(revoked)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        math::increment(&mut user_stats.challenges_closed)?;
+        
+        emit!(ChallengeClosed {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            participant: challenge.participant,
+            status: challenge.status.clone(),
+            completed_sessions: challenge.completed_sessions,
+            total_sessions: challenge.total_sessions,
+            deposit_amount: challenge.deposit_amount,
+            finalized_at: challenge.finalized_at,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_finalization_record(ctx: Context<CloseFinalizationRecord>) -> Result<()> {
+        let finalization = &ctx.accounts.finalization_record;
+        
+        // Penalties feed the reward pool, so the record must outlive the
+        // first distribution that could have counted it
+        require!(
+            finalization.rewarded
+                || finalization.reward_pool_contribution == 0
+                || finalization.timestamp < ctx.accounts.reward_state.last_distribution_time,
+            ErrorCode::RewardsNotSettled
+        );
+        
+        emit!(FinalizationRecordClosed {
+            challenge: finalization.challenge,
+            participant: finalization.participant,
+            completion_rate_percentage: finalization.completion_rate_percentage,
+            penalty_amount: finalization.penalty_amount,
+            reward_pool_contribution: finalization.reward_pool_contribution,
+            timestamp: finalization.timestamp,
+            rent_payer: finalization.rent_payer,
+        });
+        
+        Ok(())
+    }
+}
+
+// Helper functions
+const MAX_SESSION_BATCH: usize = 16;
+const MAX_CHALLENGE_TYPE_NAME_LEN: usize = 32;
+const MAX_GRACE_REASON_LEN: usize = 256;
+const MAX_GRACE_DAYS: u16 = 30;
+const MAX_PAUSE_REASON_LEN: usize = 256;
+const MAX_TEMPLATE_NAME_LEN: usize = 64;
+const MAX_TEMPLATE_VERIFIERS: usize = 8;
+pub const MAX_PHASES: usize = 52; // Weekly phases over a full year
+const MIN_MILESTONE_STEP_BPS: u16 = 40; // ceil(10000 / 255), so milestone counts fit in a u8
+const CHALLENGE_DISPUTE_WINDOW: i64 = 7 * math::SECONDS_PER_DAY;
+pub const STATS_SHARD_COUNT: u8 = 8;
+
+/// Shard a participant's protocol statistics are written to.
+pub fn stats_shard_index(participant: &Pubkey) -> u8 {
+    participant.to_bytes()[0] % STATS_SHARD_COUNT
+}
+
+/// Sums every shard into protocol-wide totals; meant for off-chain readers.
+pub fn aggregate_stats<'a>(shards: impl IntoIterator<Item = &'a StatsShard>) -> ProtocolStats {
+    shards.into_iter().fold(ProtocolStats::default(), |mut total, shard| {
+        total.challenges_created += shard.challenges_created;
+        total.volume += shard.volume as u128;
+        total.sessions_completed += shard.sessions_completed;
+        total.refunds += shard.refunds as u128;
+        total.penalties += shard.penalties as u128;
+        total.fees += shard.fees as u128;
+        total.rewards_claimed += shard.rewards_claimed as u128;
+        total
+    })
+}
+
+fn record_session(
+    challenge: &mut Account<Challenge>,
+    session: &mut Account<Session>,
+    user_stats: &mut Account<UserStats>,
+    verified_by: Pubkey,
+    proof: ProofInput,
+    session_metadata: SessionMetadata,
+    now: i64,
+) -> Result<()> {
+    let proof = register_session(challenge, user_stats, verified_by, proof, &session_metadata, now)?;
+    
+    // Store session record
+    session.challenge = challenge.key();
+    session.session_number = challenge.sessions_recorded;
+    session.timestamp = now;
+    session.phase_index = challenge.phase_index;
+    session.proof = proof;
+    session.verified_by = verified_by;
+    session.metadata = session_metadata;
+    session.auto_verified = false; // Always false since only verifiers can mark sessions
+    session.revoked = false;
+    
+    Ok(())
+}
+
+/// Validates a session and counts it against the challenge, independent of
+/// where the session record itself is stored.
+fn register_session(
+    challenge: &mut Account<Challenge>,
+    user_stats: &mut Account<UserStats>,
+    verified_by: Pubkey,
+    proof: ProofInput,
+    session_metadata: &SessionMetadata,
+    now: i64,
+) -> Result<ProofRef> {
+    // Validate challenge status
+    require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+    require!(now >= challenge.start_time, ErrorCode::ChallengeNotStarted);
+    require!(now < challenge.end_time, ErrorCode::ChallengeExpired);
+    require!(challenge.completed_sessions < challenge.total_sessions, ErrorCode::AllSessionsCompleted);
+    // A finished phase must be settled before sessions count toward the next
+    require!(
+        challenge.is_last_phase() || now < challenge.phase_end()?,
+        ErrorCode::PhaseSettlementDue
+    );
+    
+    // Validate the proof reference for its storage backend
+    let proof = proof.into_proof_ref()?;
+    
+    // Enforce the challenge's schedule against the previous sessions
+    challenge.schedule.check_session(&challenge.recent_session_times, now)?;
+    
+    // Validate session metadata based on challenge type and the participant's goal
+    validate_session_metadata(
+        &challenge.schema,
+        challenge.min_session_minutes,
+        &challenge.session_goal,
+        session_metadata,
+    )?;
+    
+    // Update challenge
+    math::increment(&mut challenge.completed_sessions)?;
+    math::increment(&mut challenge.phase_completed)?;
+    math::increment(&mut challenge.sessions_recorded)?;
+    challenge.last_session_time = now;
+    schedule::push_recent(&mut challenge.recent_session_times, now);
+    
+    // Update user stats
+    math::increment(&mut user_stats.total_sessions_completed)?;
+    user_stats.last_activity = now;
+    
+    emit!(SessionCompleted {
+        challenge: challenge.key(),
+        challenge_id: challenge.challenge_id,
+        session_number: challenge.sessions_recorded,
+        timestamp: now,
+        verified_by,
+    });
+    
+    let milestones = challenge.milestones_reached()?;
+    if milestones > challenge.milestones_passed {
+        challenge.milestones_passed = milestones;
+        emit!(MilestoneReached {
+            challenge: challenge.key(),
+            challenge_id: challenge.challenge_id,
+            milestone: milestones,
+            completed_sessions: challenge.completed_sessions,
+            refund_unlocked: challenge.milestone_refund_unlocked()?,
+        });
+    }
+    
+    // Auto-finalize if all sessions completed
+    if challenge.completed_sessions == challenge.total_sessions {
+        msg!("All sessions completed, auto-finalizing challenge");
+    }
+    
+    Ok(proof)
+}
+
+/// A challenge's leftover accounts may go once it is settled and its dispute window
+/// has passed; a closed challenge account necessarily got there first, see `close_challenge`.
+fn require_dispute_window_closed(challenge_info: &AccountInfo, now: i64) -> Result<()> {
+    if challenge_info.lamports() == 0 && challenge_info.data_is_empty() {
+        return Ok(());
+    }
+    require_keys_eq!(*challenge_info.owner, crate::ID, ErrorCode::ChallengeNotFinalized);
+    let challenge = Challenge::try_deserialize(&mut &challenge_info.try_borrow_data()?[..])?;
+    require!(challenge.is_settled(), ErrorCode::ChallengeNotFinalized);
+    require!(
+        now >= math::add_seconds(challenge.finalized_at, CHALLENGE_DISPUTE_WINDOW)?,
+        ErrorCode::DisputeWindowOpen
+    );
+    Ok(())
+}
+
+/// Whether a challenge's pledges were settled by finalization, so their accounts can go.
+/// A closed challenge has no pledges left to refund, see close_challenge.
+fn challenge_pledges_paid_out(challenge_info: &AccountInfo) -> Result<bool> {
+    if challenge_info.lamports() == 0 && challenge_info.data_is_empty() {
+        return Ok(true);
+    }
+    require_keys_eq!(*challenge_info.owner, crate::ID, ErrorCode::ChallengeNotFinalized);
+    let challenge = Challenge::try_deserialize(&mut &challenge_info.try_borrow_data()?[..])?;
+    Ok(challenge.is_settled() && challenge.status != ChallengeStatus::Cancelled)
+}
+
+/// Checks that the instruction preceding the current one is an Ed25519
+/// precompile call verifying `expected_message` signed by `expected_signer`.
+fn verify_ed25519_attestation(
+    instructions_sysvar: &AccountInfo,
+    expected_signer: &Pubkey,
+    expected_message: &[u8],
+) -> Result<()> {
+    let current_index = sysvar_instructions::load_current_index_checked(instructions_sysvar)?;
+    require!(current_index > 0, ErrorCode::AttestationSignatureMissing);
+    let ed25519_ix = sysvar_instructions::load_instruction_at_checked(
+        (current_index - 1) as usize,
+        instructions_sysvar,
+    )?;
+    require_keys_eq!(
+        ed25519_ix.program_id,
+        ed25519_program::ID,
+        ErrorCode::AttestationSignatureMissing
+    );
+    
+    // Layout: [num_signatures: u8, padding: u8, offsets: 7 x u16, ...payload]
+    let data = &ed25519_ix.data;
+    require!(data.len() >= 16 && data[0] == 1, ErrorCode::InvalidAttestation);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    let signature_ix_index = read_u16(4);
+    let public_key_offset = read_u16(6);
+    let public_key_ix_index = read_u16(8);
+    let message_offset = read_u16(10);
+    let message_size = read_u16(12);
+    let message_ix_index = read_u16(14);
+    
+    // Signature, key and message must all live inside the precompile instruction
+    // itself, otherwise the verified bytes could differ from the ones we inspect
+    let this_ix = u16::MAX as usize;
+    require!(
+        signature_ix_index == this_ix && public_key_ix_index == this_ix && message_ix_index == this_ix,
+        ErrorCode::InvalidAttestation
+    );
+    
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(ErrorCode::InvalidAttestation)?;
+    require!(public_key == expected_signer.as_ref(), ErrorCode::InvalidAttestation);
+    
+    let message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(ErrorCode::InvalidAttestation)?;
+    require!(message == expected_message, ErrorCode::InvalidAttestation);
+    
+    Ok(())
+}
+
+/// Creates a program-owned PDA the way Anchor's `init` does, including the
+/// case where the address was pre-funded to block a plain `create_account`.
+fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    target: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    signer_seeds: &[&[u8]],
+) -> Result<()> {
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+    let current_lamports = target.lamports();
+    let seeds_slice = &[signer_seeds];
+    
+    if current_lamports == 0 {
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: target.clone(),
+                },
+                seeds_slice,
+            ),
+            rent_lamports,
+            space as u64,
+            &crate::ID,
+        )?;
+    } else {
+        require!(target.data_is_empty(), ErrorCode::InvalidSessionBatch);
+        let shortfall = rent_lamports.saturating_sub(current_lamports);
+        if shortfall > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    system_program::Transfer {
+                        from: payer.clone(),
+                        to: target.clone(),
+                    },
+                ),
+                shortfall,
+            )?;
+        }
+        system_program::allocate(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::Allocate {
+                    account_to_allocate: target.clone(),
+                },
+                seeds_slice,
+            ),
+            space as u64,
+        )?;
+        system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::Assign {
+                    account_to_assign: target.clone(),
+                },
+                seeds_slice,
+            ),
+            &crate::ID,
+        )?;
+    }
+    
+    Ok(())
+}
+
+fn validate_challenge_type_params(config: &Config, params: &ChallengeTypeParams) -> Result<()> {
+    require!(
+        !params.name.is_empty() && params.name.len() <= MAX_CHALLENGE_TYPE_NAME_LEN,
+        ErrorCode::InvalidChallengeTypeConfig
+    );
+    // Per-type limits may only narrow the protocol-wide ones
+    require!(
+        config.min_deposit <= params.min_deposit
+            && params.min_deposit <= params.max_deposit
+            && params.max_deposit <= config.max_deposit,
+        ErrorCode::InvalidChallengeTypeConfig
+    );
+    require!(
+        1 <= params.min_sessions && params.min_sessions <= params.max_sessions && params.max_sessions <= 365,
+        ErrorCode::InvalidChallengeTypeConfig
+    );
+    require!(
+        7 <= params.min_duration_days
+            && params.min_duration_days <= params.max_duration_days
+            && params.max_duration_days <= 365,
+        ErrorCode::InvalidChallengeTypeConfig
+    );
+    require!(params.min_session_minutes <= 24 * 60, ErrorCode::InvalidChallengeTypeConfig);
+    require!(
+        params.default_grace_periods <= params.max_grace_periods
+            && 1 <= params.default_grace_days
+            && params.default_grace_days <= params.max_grace_days
+            && params.max_grace_days <= MAX_GRACE_DAYS,
+        ErrorCode::InvalidChallengeTypeConfig
+    );
+    require!(
+        grace_penalty_fits(params.max_grace_periods, params.grace_refund_penalty_bps),
+        ErrorCode::InvalidChallengeTypeConfig
+    );
+    
+    Ok(())
+}
+
+/// Using every grace period may at most forfeit the whole refund.
+fn grace_penalty_fits(max_grace_periods: u8, grace_refund_penalty_bps: u16) -> bool {
+    max_grace_periods as u64 * grace_refund_penalty_bps as u64 <= math::BASIS_POINTS
+}
+
+fn calculate_performance_score(stats: &UserStats) -> u64 {
+    let base_score = stats.perfect_completions as u64 * 100;
+    let streak_bonus = stats.best_streak as u64 * 10;
+    let consistency_bonus = if stats.challenges_failed == 0 { 50 } else { 0 };
+    
+    base_score + streak_bonus + consistency_bonus
+}
+
+fn calculate_total_epoch_score(_records: &[AccountInfo]) -> Result<u64> {
+    // Sum up all performance scores from finalization records
+    // Implementation depends on how you want to iterate through accounts
     Ok(1000) // Placeholder
 }
 
-fn transfer_from_vault<'info>(
-    vault: &AccountInfo<'info>,
-    to: &AccountInfo<'info>,
-    token_program: &AccountInfo<'info>,
-    mint: &Account<'info, Mint>,
-    amount: u64,
-    signer_seeds: &[&[u8]],
-) -> Result<()> {
-    let cpi_accounts = TransferChecked {
-        from: vault.clone(),
-        mint: mint.to_account_info(),
-        to: to.clone(),
-        authority: vault.clone(),
-    };
-    let seeds_slice = &[signer_seeds];
-    let cpi_ctx = CpiContext::new_with_signer(
-        token_program.clone(),
-        cpi_accounts,
-        seeds_slice,
-    );
+/// Checks a deposit against the current protocol and type limits, which may change between runs.
+fn validate_stake(config: &Config, challenge_type: &ChallengeTypeConfig, deposit_amount: u64) -> Result<()> {
+    require!(challenge_type.active, ErrorCode::ChallengeTypeInactive);
+    require!(deposit_amount >= config.min_deposit, ErrorCode::DepositTooSmall); // Min from config
+    require!(deposit_amount <= config.max_deposit, ErrorCode::DepositTooLarge); // Max from config
+    require!(deposit_amount >= challenge_type.min_deposit, ErrorCode::DepositTooSmall);
+    require!(deposit_amount <= challenge_type.max_deposit, ErrorCode::DepositTooLarge);
+    Ok(())
+}
+
+/// Validates `terms` against the protocol and type limits and initializes a fresh challenge.
+/// The caller moves the deposit into the vault.
+fn open_challenge(
+    challenge: &mut Account<Challenge>,
+    user_stats: &mut Account<UserStats>,
+    config: &Config,
+    challenge_type: &ChallengeTypeConfig,
+    participant: Pubkey,
+    terms: ChallengeTerms,
+    now: i64,
+) -> Result<()> {
+    let ChallengeTerms {
+        deposit_amount,
+        total_sessions,
+        duration_days,
+        verifier,
+        challenge_type_id,
+        session_goal,
+        schedule,
+        grace_terms,
+        grace_refund_penalty_bps,
+        start_time,
+        phase_targets,
+        milestone_step_bps,
+    } = terms;
+    
+    // Validate inputs
+    require!(!config.paused, ErrorCode::ProtocolPaused);
+    validate_stake(config, challenge_type, deposit_amount)?;
+    require!(
+        (challenge_type.min_sessions..=challenge_type.max_sessions).contains(&total_sessions),
+        ErrorCode::InvalidSessionCount
+    );
+    require!(
+        (challenge_type.min_duration_days..=challenge_type.max_duration_days).contains(&duration_days),
+        ErrorCode::InvalidDuration
+    );
+    validate_session_goal(&challenge_type.schema, &session_goal)?;
+    // A deferred start escrows the deposit now; the challenge runs from `start_time`
+    let start_time = match start_time {
+        Some(start_time) => {
+            let horizon = math::add_seconds(
+                now,
+                math::days_to_seconds(config.max_start_delay_days as u32),
+            )?;
+            require!(
+                (now..=horizon).contains(&start_time),
+                ErrorCode::InvalidStartTime
+            );
+            start_time
+        }
+        None => now,
+    };
+    let end_time = math::add_seconds(start_time, math::days_to_seconds(duration_days))?;
+    let schedule = match schedule {
+        Some(schedule) => {
+            // An explicit schedule must leave room for every session
+            schedule.validate()?;
+            require!(
+                schedule.capacity(start_time, end_time)? >= total_sessions,
+                ErrorCode::InvalidSchedule
+            );
+            schedule
+        }
+        None => Schedule::MinInterval {
+            hours: math::minimum_interval_hours(total_sessions, duration_days),
+        },
+    };
+    // Without phases the whole challenge settles as one phase at the end
+    let phase_targets = phase_targets.unwrap_or_else(|| vec![total_sessions]);
+    validate_phase_targets(&phase_targets, total_sessions, duration_days)?;
+    // Milestone refunds are opt-in
+    let milestone_step_bps = milestone_step_bps.unwrap_or(0);
+    validate_milestone_step(config, &phase_targets, milestone_step_bps)?;
+    // Participants may trade grace periods for length within the type's limits
+    let grace_terms = grace_terms.unwrap_or(GraceTerms {
+        periods: challenge_type.default_grace_periods,
+        days: challenge_type.default_grace_days,
+    });
+    require!(
+        grace_terms.periods <= challenge_type.max_grace_periods
+            && (1..=challenge_type.max_grace_days).contains(&grace_terms.days),
+        ErrorCode::InvalidGraceTerms
+    );
+    // Templates may set their own penalty; the type may have tightened since
+    let grace_refund_penalty_bps = grace_refund_penalty_bps.unwrap_or(challenge_type.grace_refund_penalty_bps);
+    require!(
+        grace_penalty_fits(challenge_type.max_grace_periods, grace_refund_penalty_bps),
+        ErrorCode::InvalidGraceTerms
+    );
+    
+    // Initialize challenge
+    challenge.participant = participant;
+    challenge.deposit_amount = deposit_amount;
+    challenge.total_sessions = total_sessions;
+    challenge.completed_sessions = 0;
+    challenge.sessions_recorded = 0;
+    challenge.start_time = start_time;
+    challenge.end_time = end_time;
+    challenge.status = ChallengeStatus::Active;
+    challenge.verifier = verifier;
+    challenge.challenge_id = user_stats.challenges_created; // Unique per participant
+    challenge.last_session_time = 0;
+    // Snapshot the type's rules so later registry updates don't affect running challenges
+    challenge.challenge_type_id = challenge_type_id;
+    challenge.schema = challenge_type.schema;
+    challenge.min_session_minutes = challenge_type.min_session_minutes;
+    challenge.session_goal = session_goal;
+    challenge.schedule = schedule;
+    challenge.recent_session_times = [0; RECENT_SESSIONS];
+    challenge.grace_periods_used = 0;
+    challenge.grace_periods_purchased = 0;
+    challenge.max_grace_periods = grace_terms.periods;
+    challenge.grace_period_days = grace_terms.days;
+    challenge.grace_refund_penalty_bps = grace_refund_penalty_bps;
+    challenge.pause_requested_at = 0;
+    challenge.paused_at = 0;
+    challenge.total_paused_seconds = 0;
+    challenge.duration_days = duration_days;
+    challenge.recurring = false;
+    challenge.rollover_amount = 0;
+    challenge.phase_targets = phase_targets;
+    challenge.phase_index = 0;
+    challenge.phase_completed = 0;
+    challenge.refunded_so_far = 0;
+    challenge.penalized_so_far = 0;
+    challenge.milestone_step_bps = milestone_step_bps;
+    challenge.milestones_passed = 0;
+    challenge.milestone_excluded_deposit = 0;
+    challenge.template = None;
+    challenge.pledged_total = 0;
+    challenge.pledge_count = 0;
+    challenge.finalized_at = 0;
+    challenge.session_root = merkle::empty_root();
+    challenge.compressed_sessions = 0;
+    
+    // Per-user counter instead of Config keeps creations from contending on one account
+    user_stats.user = participant;
+    math::increment(&mut user_stats.challenges_created)?;
+    
+    Ok(())
+}
+
+/// Extends the challenge by one grace period and fills in its record.
+fn apply_grace_period(
+    challenge: &mut Account<Challenge>,
+    grace_record: &mut GracePeriodRecord,
+    reason: String,
+    now: i64,
+) -> Result<()> {
+    require!(challenge.status == ChallengeStatus::Active, ErrorCode::ChallengeNotActive);
+    require!(reason.len() <= MAX_GRACE_REASON_LEN, ErrorCode::ReasonTooLong);
+    require!(now < challenge.end_time, ErrorCode::ChallengeExpired);
+    
+    // Extend challenge by the agreed grace length with overflow protection
+    let extension = math::days_to_seconds(challenge.grace_period_days as u32);
+    challenge.end_time = math::add_seconds(challenge.end_time, extension)?;
+    math::increment(&mut challenge.grace_periods_used)?;
+    
+    grace_record.challenge = challenge.key();
+    grace_record.participant = challenge.participant;
+    grace_record.used_at = now;
+    grace_record.reason = reason;
+    grace_record.new_end_time = challenge.end_time;
+    Ok(())
+}
+
+/// Checks a template's terms up front so participants can't be offered one that never opens.
+/// The type's limits are checked again when a challenge is created from it.
+fn validate_template_params(
+    config: &Config,
+    challenge_type: &ChallengeTypeConfig,
+    params: &ChallengeTemplateParams,
+) -> Result<()> {
+    require!(
+        !params.name.is_empty() && params.name.len() <= MAX_TEMPLATE_NAME_LEN,
+        ErrorCode::InvalidTemplate
+    );
+    require!(challenge_type.active, ErrorCode::ChallengeTypeInactive);
+    // The template's deposit bounds may only narrow the protocol and type ranges
+    require!(
+        config.min_deposit.max(challenge_type.min_deposit) <= params.min_deposit
+            && params.min_deposit <= params.max_deposit
+            && params.max_deposit <= config.max_deposit.min(challenge_type.max_deposit),
+        ErrorCode::InvalidTemplate
+    );
+    require!(
+        (challenge_type.min_sessions..=challenge_type.max_sessions).contains(&params.total_sessions),
+        ErrorCode::InvalidSessionCount
+    );
+    require!(
+        (challenge_type.min_duration_days..=challenge_type.max_duration_days).contains(&params.duration_days),
+        ErrorCode::InvalidDuration
+    );
+    validate_session_goal(&challenge_type.schema, &params.session_goal)?;
+    if let Some(schedule) = &params.schedule {
+        schedule.validate()?;
+    }
+    if let Some(grace_terms) = &params.grace_terms {
+        require!(
+            grace_terms.periods <= challenge_type.max_grace_periods
+                && (1..=challenge_type.max_grace_days).contains(&grace_terms.days),
+            ErrorCode::InvalidGraceTerms
+        );
+    }
+    if let Some(penalty_bps) = params.grace_refund_penalty_bps {
+        require!(
+            grace_penalty_fits(challenge_type.max_grace_periods, penalty_bps),
+            ErrorCode::InvalidGraceTerms
+        );
+    }
+    // An empty plan means a single phase
+    if !params.phase_targets.is_empty() {
+        validate_phase_targets(&params.phase_targets, params.total_sessions, params.duration_days)?;
+    }
+    require!(params.verifiers.len() <= MAX_TEMPLATE_VERIFIERS, ErrorCode::InvalidTemplate);
+    Ok(())
+}
+
+/// Phase session targets must be positive, at most one phase per day, and add up to the total.
+fn validate_phase_targets(targets: &[u32], total_sessions: u32, duration_days: u32) -> Result<()> {
+    require!(
+        !targets.is_empty() && targets.len() <= MAX_PHASES && targets.len() as u32 <= duration_days,
+        ErrorCode::InvalidPhasePlan
+    );
+    require!(targets.iter().all(|&target| target > 0), ErrorCode::InvalidPhasePlan);
+    let sum: u64 = targets.iter().map(|&target| target as u64).sum();
+    require!(sum == total_sessions as u64, ErrorCode::InvalidPhasePlan);
+    Ok(())
+}
+
+/// A milestone step is 0 (no milestones) or at least the protocol minimum.
+/// Phased challenges already pay out along the way, so they take no milestones.
+fn validate_milestone_step(config: &Config, phase_targets: &[u32], milestone_step_bps: u16) -> Result<()> {
+    if milestone_step_bps == 0 {
+        return Ok(());
+    }
+    require!(config.min_milestone_step_bps > 0, ErrorCode::MilestonesDisabled);
+    require!(
+        phase_targets.len() == 1
+            && milestone_step_bps >= config.min_milestone_step_bps.max(MIN_MILESTONE_STEP_BPS)
+            && milestone_step_bps as u64 <= math::BASIS_POINTS,
+        ErrorCode::InvalidMilestoneStep
+    );
+    Ok(())
+}
+
+/// The protocol fee taken from `penalty`; the reward and charity shares stay in the vault.
+fn split_penalty(config: &Config, penalty: u64) -> Result<u64> {
+    math::percentage_of(penalty, config.fee_percentage)
+}
+
+/// Whether the participant's token account lets `delegate` pull `amount` for a renewal.
+fn renewal_allowance_covers(token_account: &TokenAccount, delegate: &Pubkey, amount: u64) -> bool {
+    token_account.delegate == COption::Some(*delegate)
+        && token_account.delegated_amount >= amount
+        && token_account.amount >= amount
+}
+
+/// Price of the next grace period after `purchased` earlier purchases.
+pub fn grace_purchase_price(config: &Config, purchased: u8) -> Result<u64> {
+    let escalation = config
+        .grace_price_increment
+        .checked_mul(purchased as u64)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    math::checked_add(config.grace_price_base, escalation)
+}
+
+fn transfer_from_vault<'info>(
+    vault: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    mint: &Account<'info, Mint>,
+    amount: u64,
+    signer_seeds: &[&[u8]],
+) -> Result<()> {
+    let cpi_accounts = TransferChecked {
+        from: vault.clone(),
+        mint: mint.to_account_info(),
+        to: to.clone(),
+        authority: vault.clone(),
+    };
+    let seeds_slice = &[signer_seeds];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.clone(),
+        cpi_accounts,
+        seeds_slice,
+    );
+    
+    token_2022::transfer_checked(cpi_ctx, amount, mint.decimals)
+}
+
+// Account structures
+#[account]
+#[derive(InitSpace)]
+pub struct Config {
+    pub authority: Pubkey,
+    pub treasury: Pubkey,
+    pub accepted_mint: Pubkey,
+    pub fee_percentage: u8,
+    pub reward_percentage: u8,
+    pub charity_percentage: u8,
+    pub total_challenges: u64, // No longer updated; see StatsShard
+    pub total_volume: u64,     // No longer updated; see StatsShard
+    pub paused: bool,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    pub arbiter: Pubkey,
+    pub grace_price_base: u64,      // Price of the first purchased grace period
+    pub grace_price_increment: u64, // Added for each further purchase
+    pub max_grace_purchases: u8,    // Per challenge; 0 disables purchases
+    pub max_start_delay_days: u16,  // How far ahead a challenge may be scheduled to start
+    pub min_milestone_step_bps: u16, // Smallest milestone step a challenge may choose; 0 disables
+    pub max_pause_days: u16,        // Longest a hardship pause can extend a challenge
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Challenge {
+    pub participant: Pubkey,
+    pub deposit_amount: u64,
+    pub total_sessions: u32,
+    pub completed_sessions: u32,
+    pub sessions_recorded: u32, // Includes revoked sessions; used for session PDA seeds
+    pub start_time: i64,
+    pub end_time: i64,
+    pub last_session_time: i64,
+    pub status: ChallengeStatus,
+    pub verifier: Option<Pubkey>,
+    pub challenge_id: u64,
+    pub challenge_type_id: u16,
+    pub schema: MetadataSchema,
+    pub min_session_minutes: u16,
+    pub session_goal: SessionGoal,
+    pub schedule: Schedule,
+    pub recent_session_times: [i64; RECENT_SESSIONS], // Newest first, 0 = unused
+    pub grace_periods_used: u8, // Includes purchased grace periods; used for grace record seeds
+    pub max_grace_periods: u8,
+    pub grace_periods_purchased: u8,
+    pub grace_period_days: u16,
+    pub grace_refund_penalty_bps: u16, // Refund reduction per grace period used
+    pub pause_requested_at: i64, // 0 = no pending pause request
+    pub paused_at: i64,
+    pub total_paused_seconds: i64,
+    pub duration_days: u32, // As requested; end_time - start_time also includes grace and pauses
+    pub recurring: bool,
+    pub rollover_amount: u64, // Refund held in the vault for the next run of a recurring challenge
+    #[max_len(MAX_PHASES)]
+    pub phase_targets: Vec<u32>, // Session target per phase; a single entry for unphased challenges
+    pub phase_index: u8,
+    pub phase_completed: u32, // Sessions counted toward the current phase
+    pub refunded_so_far: u64, // Includes phase and milestone refunds
+    pub penalized_so_far: u64, // Includes pledges forfeited at finalization
+    pub milestone_step_bps: u16, // 0 = no milestone refunds
+    pub milestones_passed: u8,   // Highest milestone announced; revocations don't lower it
+    pub milestone_excluded_deposit: u64, // Top-ups that don't count toward milestones already passed
+    pub template: Option<Pubkey>, // Template the challenge was opened from
+    pub pledged_total: u64,       // Supporters' stake, settled at finalization
+    pub pledge_count: u32,
+    pub finalized_at: i64,
+    pub session_root: [u8; 32], // Merkle root of compressed sessions, see SessionTree
+    pub compressed_sessions: u32,
+}
+
+impl Challenge {
+    /// Whether the outcome is final and no more sessions can be recorded.
+    pub fn is_settled(&self) -> bool {
+        !matches!(self.status, ChallengeStatus::Active | ChallengeStatus::Paused)
+    }
+
+    pub fn is_last_phase(&self) -> bool {
+        self.phase_index as usize + 1 >= self.phase_targets.len()
+    }
+
+    pub fn phase_target(&self) -> u32 {
+        self.phase_targets[self.phase_index as usize]
+    }
+
+    /// Phases split the current start-to-end span evenly, so grace periods
+    /// and pauses stretch every phase that hasn't ended yet.
+    fn phase_boundary(&self, phase: usize) -> Result<i64> {
+        let span = math::sub_seconds(self.end_time, self.start_time)?;
+        let span = u64::try_from(span).map_err(|_| error!(ErrorCode::TimeOverflow))?;
+        let offset = math::mul_div(span, phase as u64, self.phase_targets.len() as u64)?;
+        math::add_seconds(self.start_time, offset as i64)
+    }
+
+    pub fn phase_start(&self) -> Result<i64> {
+        self.phase_boundary(self.phase_index as usize)
+    }
+
+    pub fn phase_end(&self) -> Result<i64> {
+        self.phase_boundary(self.phase_index as usize + 1)
+    }
+
+    /// Deposit share at stake in the current phase, proportional to its
+    /// session target; the last phase takes whatever is left.
+    pub fn phase_deposit(&self) -> Result<u64> {
+        if self.is_last_phase() {
+            let settled = math::checked_add(self.refunded_so_far, self.penalized_so_far)?;
+            math::checked_sub(self.deposit_amount, settled)
+        } else {
+            math::mul_div(self.deposit_amount, self.phase_target() as u64, self.total_sessions as u64)
+        }
+    }
+
+    /// Refund and penalty for settling the current phase early: the share of
+    /// its deposit earned by sessions, less grace penalties as at finalization.
+    pub fn phase_settlement(&self) -> Result<(u64, u64)> {
+        let phase_deposit = self.phase_deposit()?;
+        let phase_target = self.phase_target();
+        let counted = self.phase_completed.min(phase_target);
+        let earned = math::mul_div(phase_deposit, counted as u64, phase_target as u64)?;
+        let refund = math::mul_div(earned, self.grace_refund_factor_bps(), math::BASIS_POINTS)?;
+        Ok((refund, math::checked_sub(phase_deposit, refund)?))
+    }
+
+    /// Deposit at stake in the last phase, and how much of it milestone
+    /// refunds have already paid out. Milestones only apply to unphased challenges.
+    pub fn last_phase_stake(&self) -> Result<(u64, u64)> {
+        if self.phase_targets.len() == 1 {
+            Ok((self.deposit_amount, self.refunded_so_far))
+        } else {
+            Ok((self.phase_deposit()?, 0))
+        }
+    }
+
+    /// Seconds the current pause extends the challenge by at `now`, capped at
+    /// `max_pause`, and whether that cap has been reached.
+    pub fn pause_credit(&self, now: i64, max_pause: i64) -> Result<(i64, bool)> {
+        let elapsed = math::sub_seconds(now, self.paused_at)?;
+        Ok((elapsed.min(max_pause), elapsed >= max_pause))
+    }
+
+    /// Share of the earned refund kept after grace penalties, in basis points.
+    /// Each free grace period used may cost a share; bought ones are already paid for.
+    pub fn grace_refund_factor_bps(&self) -> u64 {
+        let free_grace_used = self.grace_periods_used - self.grace_periods_purchased;
+        let cost_bps = (free_grace_used as u64 * self.grace_refund_penalty_bps as u64).min(math::BASIS_POINTS);
+        math::BASIS_POINTS - cost_bps
+    }
+
+    /// Milestones passed with the current completed sessions.
+    pub fn milestones_reached(&self) -> Result<u8> {
+        if self.milestone_step_bps == 0 {
+            return Ok(0);
+        }
+        let completion_bps = math::basis_points(self.completed_sessions, self.total_sessions)?;
+        u8::try_from(completion_bps / self.milestone_step_bps as u64).map_err(|_| error!(ErrorCode::InvalidMilestoneStep))
+    }
+
+    /// Share of the deposit covered by `milestones`, in basis points.
+    fn milestone_bps(&self, milestones: u8) -> u64 {
+        (milestones as u64 * self.milestone_step_bps as u64).min(math::BASIS_POINTS)
+    }
+
+    /// Refund unlocked by the milestones reached so far, net of grace penalties.
+    /// Each milestone's slice is taken from the deposit it was reached with.
+    pub fn milestone_refund_unlocked(&self) -> Result<u64> {
+        let unlocked = math::mul_div(self.deposit_amount, self.milestone_bps(self.milestones_reached()?), math::BASIS_POINTS)?;
+        let unlocked = unlocked.saturating_sub(self.milestone_excluded_deposit);
+        math::mul_div(unlocked, self.grace_refund_factor_bps(), math::BASIS_POINTS)
+    }
+
+    /// Keeps a top-up of `amount` out of the milestones already passed.
+    pub fn exclude_from_passed_milestones(&mut self, amount: u64) -> Result<()> {
+        let excluded = math::mul_div(amount, self.milestone_bps(self.milestones_passed), math::BASIS_POINTS)?;
+        self.milestone_excluded_deposit = math::checked_add(self.milestone_excluded_deposit, excluded)?;
+        Ok(())
+    }
+
+    /// The next run of a recurring challenge: same terms, fresh counters, starting `now`.
+    pub fn next_run(&self, challenge_id: u64, now: i64) -> Result<Challenge> {
+        Ok(Challenge {
+            completed_sessions: 0,
+            sessions_recorded: 0,
+            start_time: now,
+            end_time: math::add_seconds(now, math::days_to_seconds(self.duration_days))?,
+            last_session_time: 0,
+            status: ChallengeStatus::Active,
+            challenge_id,
+            recent_session_times: [0; RECENT_SESSIONS],
+            grace_periods_used: 0,
+            grace_periods_purchased: 0,
+            pause_requested_at: 0,
+            paused_at: 0,
+            total_paused_seconds: 0,
+            recurring: true,
+            rollover_amount: 0,
+            phase_index: 0,
+            phase_completed: 0,
+            refunded_so_far: 0,
+            penalized_so_far: 0,
+            milestones_passed: 0,
+            milestone_excluded_deposit: 0,
+            // Only instantiations count toward a template's usage
+            template: None,
+            // Pledges back one run; supporters pledge again for the next
+            pledged_total: 0,
+            pledge_count: 0,
+            finalized_at: 0,
+            session_root: merkle::empty_root(),
+            compressed_sessions: 0,
+            ..self.clone()
+        })
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Session {
+    pub challenge: Pubkey,
+    pub session_number: u32,
+    pub timestamp: i64,
+    pub phase_index: u8, // Phase the session counted toward
+    pub proof: ProofRef,
+    pub verified_by: Pubkey,
+    pub metadata: SessionMetadata,
+    pub auto_verified: bool,
+    pub revoked: bool,
+    pub rent_payer: Pubkey, // Receives the rent back in close_session
+}
+
+/// Frontier of a challenge's compressed session tree. The root and leaf count
+/// live on the `Challenge`; leaves are only emitted in `SessionAppended`.
+#[account]
+#[derive(InitSpace)]
+pub struct SessionTree {
+    pub challenge: Pubkey,
+    pub rent_payer: Pubkey,
+    pub frontier: [[u8; 32]; SESSION_TREE_DEPTH],
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserStats {
+    pub user: Pubkey,
+    pub total_challenges: u32,
+    pub challenges_completed: u32,
+    pub challenges_partial: u32,
+    pub challenges_failed: u32,
+    pub perfect_completions: u32,
+    pub total_sessions_completed: u32,
+    pub total_deposited: u64,
+    pub total_refunded: u64,
+    pub total_penalties: u64,
+    pub total_rewards_claimed: u64,
+    pub current_streak: u32,
+    pub best_streak: u32,
+    pub last_activity: i64,
+    pub last_claim_epoch: u64,
+    pub total_grace_periods_used: u32,
+    pub total_sessions_revoked: u32,
+    pub challenges_closed: u32,
+    pub challenges_created: u64, // Seeds the next challenge PDA
+}
+
+/// One slice of the protocol-wide statistics, see `aggregate_stats`.
+#[account]
+#[derive(InitSpace)]
+pub struct StatsShard {
+    pub shard_index: u8,
+    pub challenges_created: u64,
+    pub volume: u64,
+    pub sessions_completed: u64,
+    pub refunds: u64,
+    pub penalties: u64,
+    pub fees: u64,
+    pub rewards_claimed: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct FinalizationRecord {
+    pub challenge: Pubkey,
+    pub participant: Pubkey,
+    pub completion_rate_percentage: u64,
+    pub penalty_amount: u64,
+    pub reward_pool_contribution: u64,
+    pub timestamp: i64,
+    pub rewarded: bool,
+    pub rent_payer: Pubkey, // Receives the rent back in close_finalization_record
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct RewardState {
+    pub last_epoch_processed: u64,
+    pub next_epoch_time: i64,
+    pub total_distributed: u64,
+    pub last_distribution_time: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct GracePeriodRecord {
+    pub challenge: Pubkey,
+    pub participant: Pubkey,
+    pub used_at: i64,
+    #[max_len(MAX_GRACE_REASON_LEN)]
+    pub reason: String,
+    pub new_end_time: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ChallengeTypeConfig {
+    pub type_id: u16,
+    #[max_len(MAX_CHALLENGE_TYPE_NAME_LEN)]
+    pub name: String,
+    pub schema: MetadataSchema,
+    pub min_session_minutes: u16,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    pub default_grace_periods: u8,
+    pub max_grace_periods: u8,
+    pub default_grace_days: u16,
+    pub max_grace_days: u16,
+    pub grace_refund_penalty_bps: u16,
+    pub min_sessions: u32,
+    pub max_sessions: u32,
+    pub min_duration_days: u32,
+    pub max_duration_days: u32,
+    pub active: bool,
+}
+
+impl ChallengeTypeConfig {
+    fn apply(&mut self, params: ChallengeTypeParams) {
+        self.name = params.name;
+        self.schema = params.schema;
+        self.min_session_minutes = params.min_session_minutes;
+        self.min_deposit = params.min_deposit;
+        self.max_deposit = params.max_deposit;
+        self.default_grace_periods = params.default_grace_periods;
+        self.max_grace_periods = params.max_grace_periods;
+        self.default_grace_days = params.default_grace_days;
+        self.max_grace_days = params.max_grace_days;
+        self.grace_refund_penalty_bps = params.grace_refund_penalty_bps;
+        self.min_sessions = params.min_sessions;
+        self.max_sessions = params.max_sessions;
+        self.min_duration_days = params.min_duration_days;
+        self.max_duration_days = params.max_duration_days;
+        self.active = params.active;
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct AttesterRecord {
+    pub attester: Pubkey,
+    pub registered_at: i64,
+    pub sessions_attested: u64,
+}
+
+/// A supporter's extra stake on someone else's challenge.
+#[account]
+#[derive(InitSpace)]
+pub struct Pledge {
+    pub challenge: Pubkey,
+    pub supporter: Pubkey,
+    pub amount: u64,
+    pub pledged_at: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct CoachRecord {
+    pub coach: Pubkey,
+    pub registered_at: i64,
+}
+
+/// A published program participants can open challenges from, e.g. "30-day couch to 5K".
+#[account]
+#[derive(InitSpace)]
+pub struct ChallengeTemplate {
+    pub creator: Pubkey,
+    pub template_id: u64,
+    #[max_len(MAX_TEMPLATE_NAME_LEN)]
+    pub name: String,
+    pub challenge_type_id: u16,
+    pub total_sessions: u32,
+    pub duration_days: u32,
+    pub session_goal: SessionGoal,
+    pub schedule: Option<Schedule>,      // None = default minimum interval
+    pub grace_terms: Option<GraceTerms>, // None = the type's defaults
+    pub grace_refund_penalty_bps: Option<u16>, // None = the type's penalty
+    #[max_len(MAX_PHASES)]
+    pub phase_targets: Vec<u32>,         // Empty = single phase
+    #[max_len(MAX_TEMPLATE_VERIFIERS)]
+    pub verifiers: Vec<Pubkey>,          // Empty = participant's choice
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    pub times_used: u64,
+    pub runs_completed: u64,
+    pub active: bool,
+}
+
+// Enums and types
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub enum ChallengeStatus {
+    Active,
+    Completed,
+    PartiallyCompleted,
+    Failed,
+    Cancelled,
+    Paused, // Approved hardship pause; end_time moves by the paused duration on resume
+}
+
+/// Protocol-wide totals summed from every `StatsShard`.
+#[derive(Default, Debug, PartialEq)]
+pub struct ProtocolStats {
+    pub challenges_created: u64,
+    pub volume: u128,
+    pub sessions_completed: u64,
+    pub refunds: u128,
+    pub penalties: u128,
+    pub fees: u128,
+    pub rewards_claimed: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ChallengeTypeParams {
+    pub name: String,
+    pub schema: MetadataSchema,
+    pub min_session_minutes: u16,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    pub default_grace_periods: u8,
+    pub max_grace_periods: u8,
+    pub default_grace_days: u16,
+    pub max_grace_days: u16,
+    pub grace_refund_penalty_bps: u16,
+    pub min_sessions: u32,
+    pub max_sessions: u32,
+    pub min_duration_days: u32,
+    pub max_duration_days: u32,
+    pub active: bool,
+}
+
+/// Grace allowance a participant picks at creation, within the type's limits.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct GraceTerms {
+    pub periods: u8,
+    pub days: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ChallengeTemplateParams {
+    pub name: String,
+    pub challenge_type_id: u16,
+    pub total_sessions: u32,
+    pub duration_days: u32,
+    pub session_goal: SessionGoal,
+    pub schedule: Option<Schedule>,
+    pub grace_terms: Option<GraceTerms>,
+    pub grace_refund_penalty_bps: Option<u16>,
+    pub phase_targets: Vec<u32>,
+    pub verifiers: Vec<Pubkey>,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+}
+
+/// Everything a participant commits to when opening a challenge.
+struct ChallengeTerms {
+    deposit_amount: u64,
+    total_sessions: u32,
+    duration_days: u32,
+    verifier: Option<Pubkey>,
+    challenge_type_id: u16,
+    session_goal: SessionGoal,
+    schedule: Option<Schedule>,
+    grace_terms: Option<GraceTerms>,
+    grace_refund_penalty_bps: Option<u16>,
+    start_time: Option<i64>,
+    phase_targets: Option<Vec<u32>>,
+    milestone_step_bps: Option<u16>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SessionBatchEntry {
+    pub challenge: Pubkey,
+    pub proof: ProofInput,
+    pub metadata: SessionMetadata,
+}
+
+/// Leaf of the compressed session tree, hashed as `merkle::hash_leaf(borsh(leaf))`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SessionLeaf {
+    pub challenge: Pubkey,
+    pub session_number: u32,
+    pub timestamp: i64,
+    pub proof: ProofRef,
+    pub verified_by: Pubkey,
+    pub metadata: SessionMetadata,
+}
+
+/// Payload an attester signs off-chain for `mark_session_attested`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AttestationMessage {
+    pub challenge: Pubkey,
+    pub session_number: u32,
+    pub proof: ProofInput,
+    pub metadata: SessionMetadata,
+}
+
+impl AttestationMessage {
+    pub const DOMAIN: &'static [u8] = b"disciplinator:session-attestation:v1";
+
+    pub fn to_signed_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Self::DOMAIN.to_vec();
+        self.serialize(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+// Contexts
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Config::INIT_SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    /// CHECK: Treasury account
+    pub treasury: AccountInfo<'info>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+        token::mint = accepted_mint,
+        token::authority = vault,
+        token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault_rewards", config.key().as_ref()],
+        bump,
+        token::mint = accepted_mint,
+        token::authority = vault_rewards,
+        token::token_program = token_program,
+    )]
+    pub vault_rewards: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault_reserve", config.key().as_ref()],
+        bump,
+        token::mint = accepted_mint,
+        token::authority = vault_reserve,
+        token::token_program = token_program,
+    )]
+    pub vault_reserve: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + RewardState::INIT_SPACE,
+        seeds = [b"reward_state"],
+        bump
+    )]
+    pub reward_state: Account<'info, RewardState>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    deposit_amount: u64,
+    total_sessions: u32,
+    duration_days: u32,
+    verifier: Option<Pubkey>,
+    challenge_type_id: u16,
+)]
+pub struct CreateChallenge<'info> {
+    // Must load before `challenge`, whose seeds use its counter
+    #[account(
+        init_if_needed,
+        payer = participant,
+        space = 8 + UserStats::INIT_SPACE,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        init,
+        payer = participant,
+        space = 8 + Challenge::INIT_SPACE,
+        seeds = [
+            b"challenge", 
+            participant.key().as_ref(), 
+            &user_stats.challenges_created.to_le_bytes()
+        ],
+        bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(mut)]
+    pub participant: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == participant.key(),
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(
+        seeds = [b"challenge_type".as_ref(), &challenge_type_id.to_le_bytes()],
+        bump
+    )]
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&participant.key())]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateChallengeFromTemplate<'info> {
+    // Must load before `challenge`, whose seeds use its counter
+    #[account(
+        init_if_needed,
+        payer = participant,
+        space = 8 + UserStats::INIT_SPACE,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
     
-    token_2022::transfer_checked(cpi_ctx, amount, mint.decimals)
+    #[account(
+        init,
+        payer = participant,
+        space = 8 + Challenge::INIT_SPACE,
+        seeds = [
+            b"challenge", 
+            participant.key().as_ref(), 
+            &user_stats.challenges_created.to_le_bytes()
+        ],
+        bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(
+        mut,
+        seeds = [b"template", template.creator.as_ref(), &template.template_id.to_le_bytes()],
+        bump
+    )]
+    pub template: Account<'info, ChallengeTemplate>,
+    
+    #[account(mut)]
+    pub participant: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == participant.key(),
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(
+        seeds = [b"challenge_type".as_ref(), &template.challenge_type_id.to_le_bytes()],
+        bump
+    )]
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&participant.key())]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MarkSession<'info> {
+    #[account(
+        mut,
+        constraint = challenge.verifier == Some(signer.key())
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    /// CHECK: Participant account; must own the challenge whose stats are credited
+    #[account(constraint = challenge.participant == participant.key() @ ErrorCode::UnauthorizedParticipant)]
+    pub participant: AccountInfo<'info>,
+    
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + Session::INIT_SPACE,
+        seeds = [
+            b"session", 
+            challenge.key().as_ref(),
+            &challenge.sessions_recorded.to_le_bytes()
+        ],
+        bump
+    )]
+    pub session: Account<'info, Session>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(session_number: u32)]
+pub struct MarkSessionAttested<'info> {
+    #[account(
+        mut,
+        constraint = challenge.verifier == Some(attester_record.attester) @ ErrorCode::UnauthorizedVerifier
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    /// CHECK: Participant account; must own the challenge since the relayer is untrusted
+    #[account(constraint = challenge.participant == participant.key() @ ErrorCode::UnauthorizedParticipant)]
+    pub participant: AccountInfo<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"attester", attester_record.attester.as_ref()],
+        bump
+    )]
+    pub attester_record: Account<'info, AttesterRecord>,
+    
+    /// Relayer paying for the session account; need not be the attester
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Session::INIT_SPACE,
+        seeds = [
+            b"session", 
+            challenge.key().as_ref(),
+            &challenge.sessions_recorded.to_le_bytes()
+        ],
+        bump
+    )]
+    pub session: Account<'info, Session>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    /// CHECK: Instructions sysvar, used to inspect the Ed25519 precompile call
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: AccountInfo<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MarkSessionCompressed<'info> {
+    #[account(
+        mut,
+        constraint = challenge.verifier == Some(signer.key())
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    /// CHECK: Participant account; must own the challenge whose stats are credited
+    #[account(constraint = challenge.participant == participant.key() @ ErrorCode::UnauthorizedParticipant)]
+    pub participant: AccountInfo<'info>,
+    
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + SessionTree::INIT_SPACE,
+        seeds = [b"session_tree", challenge.key().as_ref()],
+        bump
+    )]
+    pub session_tree: Account<'info, SessionTree>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub system_program: Program<'info, System>,
 }
 
-// Account structures
-#[account]
-pub struct Config {
-    pub authority: Pubkey,
-    pub treasury: Pubkey,
-    pub accepted_mint: Pubkey,
-    pub fee_percentage: u8,
-    pub reward_percentage: u8,
-    pub charity_percentage: u8,
-    pub total_challenges: u64,
-    pub total_volume: u64,
-    pub paused: bool,
-    pub min_deposit: u64,
-    pub max_deposit: u64,
+#[derive(Accounts)]
+pub struct MarkSessionsBatch<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
 }
 
-#[account]
-pub struct Challenge {
-    pub participant: Pubkey,
-    pub deposit_amount: u64,
-    pub total_sessions: u32,
-    pub completed_sessions: u32,
-    pub start_time: i64,
-    pub end_time: i64,
-    pub last_session_time: i64,
-    pub status: ChallengeStatus,
-    pub verifier: Option<Pubkey>,
-    pub challenge_id: u64,
-    pub challenge_type: ChallengeType,
-    pub minimum_interval_hours: u16,
-    pub grace_periods_used: u8,
-    pub max_grace_periods: u8,
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(
+        mut,
+        constraint = session.challenge == challenge.key()
+    )]
+    pub session: Account<'info, Session>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", challenge.participant.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub signer: Signer<'info>,
 }
 
-#[account]
-pub struct Session {
-    pub challenge: Pubkey,
-    pub session_number: u32,
-    pub timestamp: i64,
-    pub proof_ipfs_hash: String,
-    pub verified_by: Pubkey,
-    pub metadata: SessionMetadata,
-    pub auto_verified: bool,
+#[derive(Accounts)]
+pub struct FinalizeChallenge<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
+    
+    /// The participant, or anyone once the challenge has ended; pays for the finalization record
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == challenge.participant,
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = treasury_token_account.owner == config.treasury
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", challenge.participant.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    #[account(
+        init,
+        payer = caller,
+        space = 8 + FinalizationRecord::INIT_SPACE,
+        seeds = [
+            b"finalization",
+            challenge.key().as_ref()
+        ],
+        bump
+    )]
+    pub finalization_record: Account<'info, FinalizationRecord>,
+    
+    /// Required when the challenge was opened from a template
+    #[account(
+        mut,
+        constraint = challenge.template == Some(template.key()) @ ErrorCode::TemplateMismatch
+    )]
+    pub template: Option<Account<'info, ChallengeTemplate>>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
 }
 
-#[account]
-pub struct UserStats {
-    pub user: Pubkey,
-    pub total_challenges: u32,
-    pub challenges_completed: u32,
-    pub challenges_partial: u32,
-    pub challenges_failed: u32,
-    pub perfect_completions: u32,
-    pub total_sessions_completed: u32,
-    pub total_deposited: u64,
-    pub total_refunded: u64,
-    pub total_penalties: u64,
-    pub total_rewards_claimed: u64,
-    pub current_streak: u32,
-    pub best_streak: u32,
-    pub last_activity: i64,
-    pub last_claim_epoch: u64,
+#[derive(Accounts)]
+pub struct SettlePhase<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == challenge.participant,
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = treasury_token_account.owner == config.treasury
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", challenge.participant.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
-#[account]
-pub struct FinalizationRecord {
-    pub challenge: Pubkey,
-    pub participant: Pubkey,
-    pub completion_rate_percentage: u64,
-    pub penalty_amount: u64,
-    pub reward_pool_contribution: u64,
-    pub timestamp: i64,
-    pub rewarded: bool,
+#[derive(Accounts)]
+pub struct ClaimMilestoneRefund<'info> {
+    #[account(
+        mut,
+        constraint = challenge.participant == participant.key()
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    pub participant: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == participant.key(),
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&participant.key())]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
-#[account]
-pub struct RewardState {
-    pub last_epoch_processed: u64,
-    pub next_epoch_time: i64,
-    pub total_distributed: u64,
+#[derive(Accounts)]
+pub struct UseGracePeriod<'info> {
+    #[account(
+        mut,
+        constraint = challenge.participant == participant.key()
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(mut)]
+    pub participant: Signer<'info>,
+    
+    #[account(
+        init,
+        payer = participant,
+        space = 8 + GracePeriodRecord::INIT_SPACE,
+        seeds = [
+            b"grace",
+            challenge.key().as_ref(),
+            &challenge.grace_periods_used.to_le_bytes()
+        ],
+        bump
+    )]
+    pub grace_record: Account<'info, GracePeriodRecord>,
+    
+    pub system_program: Program<'info, System>,
 }
 
-#[account]
-pub struct GracePeriodRecord {
-    pub challenge: Pubkey,
-    pub used_at: i64,
-    pub reason: String,
-    pub new_end_time: i64,
+#[derive(Accounts)]
+pub struct BuyGracePeriod<'info> {
+    #[account(
+        mut,
+        constraint = challenge.participant == participant.key()
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(mut)]
+    pub participant: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == participant.key(),
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault_rewards", config.key().as_ref()],
+        bump,
+    )]
+    pub vault_rewards: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = participant,
+        space = 8 + GracePeriodRecord::INIT_SPACE,
+        seeds = [
+            b"grace",
+            challenge.key().as_ref(),
+            &challenge.grace_periods_used.to_le_bytes()
+        ],
+        bump
+    )]
+    pub grace_record: Account<'info, GracePeriodRecord>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
 }
 
-// Enums and types
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
-pub enum ChallengeStatus {
-    Active,
-    Completed,
-    PartiallyCompleted,
-    Failed,
-    Cancelled,
+#[derive(Accounts)]
+pub struct SetRecurring<'info> {
+    #[account(
+        mut,
+        constraint = challenge.participant == participant.key()
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    pub participant: Signer<'info>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
-pub enum ChallengeType {
-    Fitness,
-    Education,
-    Meditation,
-    Custom,
+#[derive(Accounts)]
+pub struct RenewChallenge<'info> {
+    #[account(mut)]
+    pub previous_challenge: Account<'info, Challenge>,
+    
+    // Must load before `next_challenge`, whose seeds use its counter
+    #[account(
+        mut,
+        seeds = [b"user_stats", previous_challenge.participant.as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        init,
+        payer = keeper,
+        space = 8 + Challenge::INIT_SPACE,
+        seeds = [
+            b"challenge",
+            previous_challenge.participant.as_ref(),
+            &user_stats.challenges_created.to_le_bytes()
+        ],
+        bump
+    )]
+    pub next_challenge: Account<'info, Challenge>,
+    
+    /// Anyone may crank a renewal; they pay the new account's rent
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == previous_challenge.participant,
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(
+        seeds = [b"challenge_type".as_ref(), &previous_challenge.challenge_type_id.to_le_bytes()],
+        bump
+    )]
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    /// CHECK: PDA the participant approves as delegate for renewal top-ups
+    #[account(
+        seeds = [b"renewal_authority", config.key().as_ref()],
+        bump
+    )]
+    pub renewal_authority: AccountInfo<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&previous_challenge.participant)]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SessionMetadata {
-    pub duration_minutes: Option<u16>,
-    pub location: Option<String>,
-    pub notes: Option<String>,
+#[derive(Accounts)]
+pub struct ReleaseRollover<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
+    
+    /// The participant, or a keeper once the renewal allowance has run out
+    pub signer: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == challenge.participant,
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    /// CHECK: Renewal delegate PDA, only compared against the token account's delegate
+    #[account(
+        seeds = [b"renewal_authority", config.key().as_ref()],
+        bump
+    )]
+    pub renewal_authority: AccountInfo<'info>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
-// Contexts
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct IncreaseDeposit<'info> {
+    #[account(
+        mut,
+        constraint = challenge.participant == participant.key()
+    )]
+    pub challenge: Account<'info, Challenge>,
+    
+    pub participant: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = participant_token_account.owner == participant.key(),
+        constraint = participant_token_account.mint == config.accepted_mint,
+    )]
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    
     #[account(
-        init,
-        payer = authority,
-        space = 8 + Config::INIT_SPACE,
         seeds = [b"config"],
         bump
     )]
     pub config: Account<'info, Config>,
     
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    #[account(
+        seeds = [b"challenge_type".as_ref(), &challenge.challenge_type_id.to_le_bytes()],
+        bump
+    )]
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
     
-    /// CHECK: Treasury account
-    pub treasury: AccountInfo<'info>,
+    /// Required when the challenge was opened from a template
+    #[account(constraint = challenge.template == Some(template.key()) @ ErrorCode::TemplateMismatch)]
+    pub template: Option<Account<'info, ChallengeTemplate>>,
     
     pub accepted_mint: Account<'info, Mint>,
     
     #[account(
-        init,
-        payer = authority,
+        mut,
         seeds = [b"vault", config.key().as_ref()],
         bump,
-        token::mint = accepted_mint,
-        token::authority = vault,
-        token::token_program = token_program,
     )]
     pub vault: InterfaceAccount<'info, TokenAccount>,
     
     #[account(
-        init,
-        payer = authority,
-        seeds = [b"vault_rewards", config.key().as_ref()],
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&participant.key())]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PledgeToChallenge<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(
+        init_if_needed,
+        payer = supporter,
+        space = 8 + Pledge::INIT_SPACE,
+        seeds = [b"pledge", challenge.key().as_ref(), supporter.key().as_ref()],
+        bump
+    )]
+    pub pledge: Account<'info, Pledge>,
+    
+    #[account(mut)]
+    pub supporter: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = supporter_token_account.owner == supporter.key(),
+        constraint = supporter_token_account.mint == config.accepted_mint,
+    )]
+    pub supporter_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
         bump,
-        token::mint = accepted_mint,
-        token::authority = vault_rewards,
-        token::token_program = token_program,
     )]
-    pub vault_rewards: InterfaceAccount<'info, TokenAccount>,
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RefundPledge<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
+    
+    #[account(
+        mut,
+        close = supporter,
+        seeds = [b"pledge", challenge.key().as_ref(), supporter.key().as_ref()],
+        bump
+    )]
+    pub pledge: Account<'info, Pledge>,
+    
+    #[account(mut)]
+    pub supporter: Signer<'info>,
     
     #[account(
-        init,
-        payer = authority,
-        seeds = [b"vault_reserve", config.key().as_ref()],
-        bump,
-        token::mint = accepted_mint,
-        token::authority = vault_reserve,
-        token::token_program = token_program,
+        mut,
+        constraint = supporter_token_account.owner == supporter.key(),
+        constraint = supporter_token_account.mint == config.accepted_mint,
     )]
-    pub vault_reserve: InterfaceAccount<'info, TokenAccount>,
+    pub supporter_token_account: InterfaceAccount<'info, TokenAccount>,
     
     #[account(
-        init,
-        payer = authority,
-        space = 8 + RewardState::INIT_SPACE,
-        seeds = [b"reward_state"],
+        seeds = [b"config"],
         bump
     )]
-    pub reward_state: Account<'info, RewardState>,
+    pub config: Account<'info, Config>,
+    
+    pub accepted_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
     
     pub token_program: Interface<'info, TokenInterface>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct CreateChallenge<'info> {
+pub struct CancelChallenge<'info> {
     #[account(
-        init,
-        payer = participant,
-        space = 8 + Challenge::INIT_SPACE,
-        seeds = [
-            b"challenge", 
-            participant.key().as_ref(), 
-            &config.total_challenges.to_le_bytes()
-        ],
-        bump
+        mut,
+        constraint = challenge.participant == participant.key()
     )]
     pub challenge: Account<'info, Challenge>,
     
-    #[account(mut)]
     pub participant: Signer<'info>,
     
     #[account(
@@ -774,7 +3858,6 @@ pub struct CreateChallenge<'info> {
     pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
     
     #[account(
-        mut,
         seeds = [b"config"],
         bump
     )]
@@ -790,72 +3873,111 @@ pub struct CreateChallenge<'info> {
     pub vault: InterfaceAccount<'info, TokenAccount>,
     
     #[account(
-        init_if_needed,
-        payer = participant,
-        space = 8 + UserStats::INIT_SPACE,
-        seeds = [b"user_stats", participant.key().as_ref()],
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&participant.key())]],
         bump
     )]
-    pub user_stats: Account<'info, UserStats>,
+    pub stats_shard: Account<'info, StatsShard>,
     
     pub token_program: Interface<'info, TokenInterface>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct MarkSession<'info> {
+pub struct PauseChallenge<'info> {
     #[account(
         mut,
-        constraint = challenge.verifier == Some(signer.key())
+        constraint = challenge.participant == participant.key()
     )]
     pub challenge: Account<'info, Challenge>,
     
-    /// CHECK: Participant account
-    pub participant: AccountInfo<'info>,
-    
+    pub participant: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApprovePause<'info> {
     #[account(mut)]
-    pub signer: Signer<'info>,
+    pub challenge: Account<'info, Challenge>,
     
     #[account(
-        init,
-        payer = signer,
-        space = 8 + Session::INIT_SPACE,
-        seeds = [
-            b"session", 
-            challenge.key().as_ref(),
-            &challenge.completed_sessions.to_le_bytes()
-        ],
+        seeds = [b"config"],
         bump
     )]
-    pub session: Account<'info, Session>,
+    pub config: Account<'info, Config>,
+    
+    /// The challenge's verifier or the protocol arbiter
+    pub approver: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResumeChallenge<'info> {
+    #[account(mut)]
+    pub challenge: Account<'info, Challenge>,
     
     #[account(
-        mut,
-        seeds = [b"user_stats", participant.key().as_ref()],
+        seeds = [b"config"],
         bump
     )]
-    pub user_stats: Account<'info, UserStats>,
+    pub config: Account<'info, Config>,
     
-    pub system_program: Program<'info, System>,
+    /// The participant, or anyone once the maximum pause has passed
+    pub caller: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct FinalizeChallenge<'info> {
+pub struct DistributeRewards<'info> {
     #[account(
         mut,
-        constraint = challenge.participant == participant.key()
+        seeds = [b"reward_state"],
+        bump
     )]
-    pub challenge: Account<'info, Challenge>,
+    pub reward_state: Account<'info, RewardState>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(
+        seeds = [b"vault", config.key().as_ref()],
+        bump,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
     
+    #[account(
+        seeds = [b"vault_reserve", config.key().as_ref()],
+        bump,
+    )]
+    pub vault_reserve: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
     #[account(mut)]
     pub participant: Signer<'info>,
     
     #[account(
         mut,
-        constraint = participant_token_account.owner == participant.key(),
-        constraint = participant_token_account.mint == config.accepted_mint,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
     )]
-    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub user_stats: Account<'info, UserStats>,
+    
+    #[account(
+        mut,
+        seeds = [b"stats_shard".as_ref(), &[stats_shard_index(&participant.key())]],
+        bump
+    )]
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    #[account(
+        seeds = [b"reward_state"],
+        bump
+    )]
+    pub reward_state: Account<'info, RewardState>,
     
     #[account(
         seeds = [b"config"],
@@ -867,75 +3989,183 @@ pub struct FinalizeChallenge<'info> {
     
     #[account(
         mut,
-        seeds = [b"vault", config.key().as_ref()],
-        bump,
+        constraint = participant_token_account.owner == participant.key(),
+        constraint = participant_token_account.mint == config.accepted_mint,
     )]
-    pub vault: InterfaceAccount<'info, TokenAccount>,
+    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
     
     #[account(
         mut,
-        constraint = treasury_token_account.owner == config.treasury
+        seeds = [b"vault_rewards", config.key().as_ref()],
+        bump,
     )]
-    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub vault_rewards: InterfaceAccount<'info, TokenAccount>,
     
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PauseProtocol<'info> {
     #[account(
         mut,
-        seeds = [b"user_stats", participant.key().as_ref()],
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(shard_index: u8)]
+pub struct InitStatsShard<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + StatsShard::INIT_SPACE,
+        seeds = [b"stats_shard".as_ref(), &[shard_index]],
         bump
     )]
-    pub user_stats: Account<'info, UserStats>,
+    pub stats_shard: Account<'info, StatsShard>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
     
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(type_id: u16)]
+pub struct CreateChallengeType<'info> {
     #[account(
         init,
-        payer = participant,
-        space = 8 + FinalizationRecord::INIT_SPACE,
-        seeds = [
-            b"finalization",
-            challenge.key().as_ref()
-        ],
+        payer = authority,
+        space = 8 + ChallengeTypeConfig::INIT_SPACE,
+        seeds = [b"challenge_type".as_ref(), &type_id.to_le_bytes()],
         bump
     )]
-    pub finalization_record: Account<'info, FinalizationRecord>,
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
     
-    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct UseGracePeriod<'info> {
+pub struct UpdateChallengeType<'info> {
     #[account(
         mut,
-        constraint = challenge.participant == participant.key()
+        seeds = [b"challenge_type".as_ref(), &challenge_type.type_id.to_le_bytes()],
+        bump
     )]
-    pub challenge: Account<'info, Challenge>,
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(attester: Pubkey)]
+pub struct RegisterAttester<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + AttesterRecord::INIT_SPACE,
+        seeds = [b"attester", attester.as_ref()],
+        bump
+    )]
+    pub attester_record: Account<'info, AttesterRecord>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
     
     #[account(mut)]
-    pub participant: Signer<'info>,
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAttester<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"attester", attester_record.attester.as_ref()],
+        bump
+    )]
+    pub attester_record: Account<'info, AttesterRecord>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
     
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(coach: Pubkey)]
+pub struct RegisterCoach<'info> {
     #[account(
         init,
-        payer = participant,
-        space = 8 + GracePeriodRecord::INIT_SPACE,
-        seeds = [
-            b"grace",
-            challenge.key().as_ref(),
-            &challenge.grace_periods_used.to_le_bytes()
-        ],
+        payer = authority,
+        space = 8 + CoachRecord::INIT_SPACE,
+        seeds = [b"coach", coach.as_ref()],
         bump
     )]
-    pub grace_record: Account<'info, GracePeriodRecord>,
+    pub coach_record: Account<'info, CoachRecord>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+        constraint = config.authority == authority.key()
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
     
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct DistributeRewards<'info> {
+pub struct RevokeCoach<'info> {
     #[account(
         mut,
-        seeds = [b"reward_state"],
+        close = authority,
+        seeds = [b"coach", coach_record.coach.as_ref()],
         bump
     )]
-    pub reward_state: Account<'info, RewardState>,
+    pub coach_record: Account<'info, CoachRecord>,
     
     #[account(
         seeds = [b"config"],
@@ -944,169 +4174,554 @@ pub struct DistributeRewards<'info> {
     )]
     pub config: Account<'info, Config>,
     
+    #[account(mut)]
     pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u64, params: ChallengeTemplateParams)]
+pub struct CreateChallengeTemplate<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + ChallengeTemplate::INIT_SPACE,
+        seeds = [b"template", creator.key().as_ref(), &template_id.to_le_bytes()],
+        bump
+    )]
+    pub template: Account<'info, ChallengeTemplate>,
     
+    /// Present when the creator is a registered coach rather than the authority
     #[account(
-        seeds = [b"vault", config.key().as_ref()],
-        bump,
+        seeds = [b"coach", creator.key().as_ref()],
+        bump
     )]
-    pub vault: InterfaceAccount<'info, TokenAccount>,
+    pub coach_record: Option<Account<'info, CoachRecord>>,
     
     #[account(
-        seeds = [b"vault_reserve", config.key().as_ref()],
-        bump,
+        seeds = [b"config"],
+        bump
     )]
-    pub vault_reserve: InterfaceAccount<'info, TokenAccount>,
+    pub config: Account<'info, Config>,
+    
+    #[account(
+        seeds = [b"challenge_type".as_ref(), &params.challenge_type_id.to_le_bytes()],
+        bump
+    )]
+    pub challenge_type: Account<'info, ChallengeTypeConfig>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimRewards<'info> {
+pub struct SetTemplateActive<'info> {
+    #[account(
+        mut,
+        constraint = template.creator == creator.key() @ ErrorCode::Unauthorized
+    )]
+    pub template: Account<'info, ChallengeTemplate>,
+    
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSession<'info> {
+    /// CHECK: The session's challenge; may already be closed, see require_dispute_window_closed
+    pub challenge: AccountInfo<'info>,
+    
+    #[account(
+        mut,
+        close = rent_payer,
+        constraint = session.challenge == challenge.key(),
+        constraint = session.rent_payer == rent_payer.key() @ ErrorCode::Unauthorized
+    )]
+    pub session: Account<'info, Session>,
+    
     #[account(mut)]
-    pub participant: Signer<'info>,
+    pub rent_payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSessionTree<'info> {
+    /// CHECK: The tree's challenge; may already be closed, see require_dispute_window_closed
+    pub challenge: AccountInfo<'info>,
     
     #[account(
         mut,
-        seeds = [b"user_stats", participant.key().as_ref()],
-        bump
+        close = rent_payer,
+        seeds = [b"session_tree", challenge.key().as_ref()],
+        bump,
+        constraint = session_tree.rent_payer == rent_payer.key() @ ErrorCode::Unauthorized
     )]
-    pub user_stats: Account<'info, UserStats>,
+    pub session_tree: Account<'info, SessionTree>,
+    
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseGraceRecord<'info> {
+    /// CHECK: The record's challenge; may already be closed, see require_dispute_window_closed
+    pub challenge: AccountInfo<'info>,
     
     #[account(
-        seeds = [b"reward_state"],
-        bump
+        mut,
+        close = participant,
+        constraint = grace_record.challenge == challenge.key(),
+        constraint = grace_record.participant == participant.key()
     )]
-    pub reward_state: Account<'info, RewardState>,
+    pub grace_record: Account<'info, GracePeriodRecord>,
+    
+    #[account(mut)]
+    pub participant: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePledge<'info> {
+    /// CHECK: The pledge's challenge; may already be closed, see challenge_pledges_paid_out
+    pub challenge: AccountInfo<'info>,
     
     #[account(
-        seeds = [b"config"],
+        mut,
+        close = supporter,
+        seeds = [b"pledge", challenge.key().as_ref(), supporter.key().as_ref()],
         bump
     )]
-    pub config: Account<'info, Config>,
-    
-    pub accepted_mint: Account<'info, Mint>,
+    pub pledge: Account<'info, Pledge>,
     
+    #[account(mut)]
+    pub supporter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseChallenge<'info> {
     #[account(
         mut,
-        constraint = participant_token_account.owner == participant.key(),
-        constraint = participant_token_account.mint == config.accepted_mint,
+        close = participant,
+        constraint = challenge.participant == participant.key()
     )]
-    pub participant_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub challenge: Account<'info, Challenge>,
     
     #[account(
         mut,
-        seeds = [b"vault_rewards", config.key().as_ref()],
-        bump,
+        seeds = [b"user_stats", participant.key().as_ref()],
+        bump
     )]
-    pub vault_rewards: InterfaceAccount<'info, TokenAccount>,
+    pub user_stats: Account<'info, UserStats>,
     
-    pub token_program: Interface<'info, TokenInterface>,
+    #[account(mut)]
+    pub participant: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct PauseProtocol<'info> {
+pub struct CloseFinalizationRecord<'info> {
     #[account(
         mut,
-        seeds = [b"config"],
+        close = rent_payer,
+        seeds = [
+            b"finalization",
+            finalization_record.challenge.as_ref()
+        ],
         bump,
-        constraint = config.authority == authority.key()
+        constraint = finalization_record.rent_payer == rent_payer.key() @ ErrorCode::Unauthorized
     )]
-    pub config: Account<'info, Config>,
+    pub finalization_record: Account<'info, FinalizationRecord>,
     
-    pub authority: Signer<'info>,
+    #[account(
+        seeds = [b"reward_state"],
+        bump
+    )]
+    pub reward_state: Account<'info, RewardState>,
+    
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+}
+
+// Events
+#[event]
+pub struct ChallengeCreated {
+    pub participant: Pubkey,
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub deposit_amount: u64,
+    pub total_sessions: u32,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub challenge_type_id: u16,
+}
+
+#[event]
+pub struct SessionCompleted {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub session_number: u32,
+    pub timestamp: i64,
+    pub verified_by: Pubkey,
+}
+
+#[event]
+pub struct ChallengeFinalized {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub refund_amount: u64,
+    pub penalty_amount: u64,
+    pub completion_rate_percentage: u64,
+    pub status: ChallengeStatus,
+    pub missed_windows: u32,
+}
+
+#[event]
+pub struct GracePeriodUsed {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub grace_periods_remaining: u8,
+    pub new_end_time: i64,
+    pub extension_days: u16,
+}
+
+#[event]
+pub struct RewardsDistributed {
+    pub epoch: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub epoch: u64,
+    pub performance_score: u64,
+}
+
+#[event]
+pub struct ProtocolPaused {
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProtocolUnpaused {
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionRevoked {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub session_number: u32,
+    pub revoked_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GracePeriodPurchased {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub price: u64,
+    pub grace_periods_purchased: u8,
+    pub new_end_time: i64,
+    pub extension_days: u16,
+}
+
+#[event]
+pub struct PledgeMade {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub supporter: Pubkey,
+    pub amount: u64,
+    pub pledged_total: u64,
+}
+
+#[event]
+pub struct PledgesSettled {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub total: u64,
+    pub pledge_count: u32,
+    pub paid_to_participant: bool,
+}
+
+#[event]
+pub struct PledgeRefunded {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub supporter: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PledgeClosed {
+    pub challenge: Pubkey,
+    pub supporter: Pubkey,
+    pub amount: u64,
+    pub pledged_at: i64,
+}
+
+#[event]
+pub struct TemplateCreated {
+    pub template: Pubkey,
+    pub creator: Pubkey,
+    pub template_id: u64,
+    pub name: String,
+    pub challenge_type_id: u16,
+}
+
+#[event]
+pub struct TemplateActiveUpdated {
+    pub template: Pubkey,
+    pub active: bool,
+}
+
+#[event]
+pub struct TemplateUsed {
+    pub template: Pubkey,
+    pub participant: Pubkey,
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub times_used: u64,
+}
+
+#[event]
+pub struct CoachRegistered {
+    pub coach: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CoachRevoked {
+    pub coach: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MilestoneReached {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub milestone: u8,
+    pub completed_sessions: u32,
+    pub refund_unlocked: u64,
+}
+
+#[event]
+pub struct MilestoneRefundClaimed {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub refunded_so_far: u64,
+}
+
+#[event]
+pub struct MinMilestoneStepUpdated {
+    pub authority: Pubkey,
+    pub min_milestone_step_bps: u16,
 }
 
-// Space implementations
-impl Config {
-    pub const INIT_SPACE: usize = 32 + 32 + 32 + 1 + 1 + 1 + 8 + 8 + 1 + 8 + 8;
+#[event]
+pub struct PhaseSettled {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub phase_index: u8,
+    pub sessions_counted: u32,
+    pub session_target: u32,
+    pub refund_amount: u64,
+    pub penalty_amount: u64,
 }
 
-impl Challenge {
-    pub const INIT_SPACE: usize = 32 + 8 + 4 + 4 + 8 + 8 + 8 + 1 + 33 + 8 + 1 + 2 + 1 + 1;
+#[event]
+pub struct RecurringUpdated {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub recurring: bool,
 }
 
-impl Session {
-    pub const INIT_SPACE: usize = 32 + 4 + 8 + 64 + 32 + 100 + 1; // Assuming metadata ~100 bytes
+#[event]
+pub struct ChallengeRenewed {
+    pub participant: Pubkey,
+    pub previous_challenge: Pubkey,
+    pub previous_challenge_id: u64,
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub deposit_amount: u64,
+    pub rolled_over: u64,
+    pub pulled_from_allowance: u64,
+    pub end_time: i64,
 }
 
-impl UserStats {
-    pub const INIT_SPACE: usize = 32 + 4 + 4 + 4 + 4 + 4 + 4 + 8 + 8 + 8 + 8 + 4 + 4 + 8 + 8;
+#[event]
+pub struct RenewalStopped {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub released_amount: u64,
+    pub stopped_by: Pubkey,
 }
 
-impl FinalizationRecord {
-    pub const INIT_SPACE: usize = 32 + 32 + 8 + 8 + 8 + 8 + 1;
+#[event]
+pub struct DepositIncreased {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub new_deposit_amount: u64,
+    pub timestamp: i64,
 }
 
-impl RewardState {
-    pub const INIT_SPACE: usize = 8 + 8 + 8;
+#[event]
+pub struct ChallengeCancelled {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub refund_amount: u64,
+    pub timestamp: i64,
 }
 
-impl GracePeriodRecord {
-    pub const INIT_SPACE: usize = 32 + 8 + 256 + 8; // 256 bytes for reason string
+#[event]
+pub struct MaxStartDelayUpdated {
+    pub authority: Pubkey,
+    pub max_start_delay_days: u16,
 }
 
-// Events
 #[event]
-pub struct ChallengeCreated {
-    pub participant: Pubkey,
-    pub challenge_id: u64,
-    pub deposit_amount: u64,
-    pub total_sessions: u32,
-    pub end_time: i64,
-    pub challenge_type: ChallengeType,
+pub struct MaxPauseDurationUpdated {
+    pub authority: Pubkey,
+    pub max_pause_days: u16,
 }
 
 #[event]
-pub struct SessionCompleted {
+pub struct PauseRequested {
+    pub challenge: Pubkey,
     pub challenge_id: u64,
-    pub session_number: u32,
+    pub participant: Pubkey,
+    pub reason: String,
     pub timestamp: i64,
-    pub verified_by: Pubkey,
 }
 
 #[event]
-pub struct ChallengeFinalized {
+pub struct PauseRequestWithdrawn {
+    pub challenge: Pubkey,
     pub challenge_id: u64,
     pub participant: Pubkey,
-    pub refund_amount: u64,
-    pub penalty_amount: u64,
-    pub completion_rate_percentage: u64,
-    pub status: ChallengeStatus,
+    pub timestamp: i64,
 }
 
 #[event]
-pub struct GracePeriodUsed {
+pub struct ChallengePaused {
+    pub challenge: Pubkey,
     pub challenge_id: u64,
-    pub grace_periods_remaining: u8,
+    pub approved_by: Pubkey,
+    pub paused_at: i64,
+}
+
+#[event]
+pub struct ChallengeResumed {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub paused_seconds: i64,
     pub new_end_time: i64,
 }
 
 #[event]
-pub struct RewardsDistributed {
-    pub epoch: u64,
-    pub amount: u64,
-    pub timestamp: i64,
+pub struct GracePricingUpdated {
+    pub authority: Pubkey,
+    pub base_price: u64,
+    pub price_increment: u64,
+    pub max_purchases: u8,
 }
 
 #[event]
-pub struct RewardsClaimed {
-    pub participant: Pubkey,
-    pub amount: u64,
-    pub epoch: u64,
-    pub performance_score: u64,
+pub struct ArbiterUpdated {
+    pub authority: Pubkey,
+    pub arbiter: Pubkey,
 }
 
 #[event]
-pub struct ProtocolPaused {
+pub struct ChallengeTypeUpdated {
+    pub type_id: u16,
+    pub name: String,
+    pub active: bool,
+}
+
+#[event]
+pub struct AttesterRegistered {
+    pub attester: Pubkey,
     pub authority: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct ProtocolUnpaused {
+pub struct AttesterRevoked {
+    pub attester: Pubkey,
     pub authority: Pubkey,
     pub timestamp: i64,
 }
 
+#[event]
+pub struct SessionClosed {
+    pub challenge: Pubkey,
+    pub session_number: u32,
+    pub timestamp: i64,
+    pub proof: ProofRef,
+    pub verified_by: Pubkey,
+    pub metadata: SessionMetadata,
+    pub revoked: bool,
+    pub rent_payer: Pubkey,
+}
+
+#[event]
+pub struct SessionAppended {
+    pub leaf: SessionLeaf,
+    pub leaf_index: u32,
+    pub root: [u8; 32],
+}
+
+#[event]
+pub struct SessionTreeClosed {
+    pub challenge: Pubkey,
+    pub rent_payer: Pubkey,
+}
+
+#[event]
+pub struct GracePeriodRecordClosed {
+    pub challenge: Pubkey,
+    pub used_at: i64,
+    pub reason: String,
+    pub new_end_time: i64,
+}
+
+#[event]
+pub struct ChallengeClosed {
+    pub challenge: Pubkey,
+    pub challenge_id: u64,
+    pub participant: Pubkey,
+    pub status: ChallengeStatus,
+    pub completed_sessions: u32,
+    pub total_sessions: u32,
+    pub deposit_amount: u64,
+    pub finalized_at: i64,
+}
+
+#[event]
+pub struct FinalizationRecordClosed {
+    pub challenge: Pubkey,
+    pub participant: Pubkey,
+    pub completion_rate_percentage: u64,
+    pub penalty_amount: u64,
+    pub reward_pool_contribution: u64,
+    pub timestamp: i64,
+    pub rent_payer: Pubkey,
+}
+
 // Error codes
 #[error_code]
 pub enum ErrorCode {
@@ -1168,4 +4783,811 @@ pub enum ErrorCode {
     InsufficientRewards,
     #[msg("Epoch not ready for processing")]
     EpochNotReady,
-}
\ No newline at end of file
+    #[msg("Ed25519 attestation signature instruction missing")]
+    AttestationSignatureMissing,
+    #[msg("Invalid session attestation")]
+    InvalidAttestation,
+    #[msg("Invalid session batch")]
+    InvalidSessionBatch,
+    #[msg("Session already revoked")]
+    SessionAlreadyRevoked,
+    #[msg("Invalid Arweave transaction id")]
+    InvalidArweaveTx,
+    #[msg("Invalid proof URL (https only)")]
+    InvalidProofUrl,
+    #[msg("Invalid proof digest")]
+    InvalidProofDigest,
+    #[msg("Session details do not match challenge type")]
+    SessionDetailsMismatch,
+    #[msg("Invalid session details")]
+    InvalidSessionDetails,
+    #[msg("Session goal does not match challenge type")]
+    InvalidSessionGoal,
+    #[msg("Session does not meet the challenge goal")]
+    SessionGoalNotMet,
+    #[msg("Invalid challenge type configuration")]
+    InvalidChallengeTypeConfig,
+    #[msg("Challenge type is not active")]
+    ChallengeTypeInactive,
+    #[msg("Session location too long")]
+    LocationTooLong,
+    #[msg("Session notes too long")]
+    NotesTooLong,
+    #[msg("Grace period reason too long")]
+    ReasonTooLong,
+    #[msg("Challenge has not been finalized")]
+    ChallengeNotFinalized,
+    #[msg("Rewards referencing this record are not settled yet")]
+    RewardsNotSettled,
+    #[msg("Dispute window has not elapsed yet")]
+    DisputeWindowOpen,
+    #[msg("Compressed session tree is full")]
+    SessionTreeFull,
+    #[msg("Invalid stats shard")]
+    InvalidStatsShard,
+    #[msg("Invalid session schedule")]
+    InvalidSchedule,
+    #[msg("Weekly session limit reached")]
+    WeeklyLimitReached,
+    #[msg("Sessions are not scheduled on this day")]
+    NotAScheduledDay,
+    #[msg("A session was already recorded today")]
+    AlreadyCheckedInToday,
+    #[msg("Grace terms exceed the challenge type's limits")]
+    InvalidGraceTerms,
+    #[msg("Free grace periods must be used before buying more")]
+    GracePeriodsStillAvailable,
+    #[msg("No more grace periods can be purchased for this challenge")]
+    NoGracePurchasesLeft,
+    #[msg("Grace periods cannot be free when purchases are enabled")]
+    InvalidGracePricing,
+    #[msg("A pause has already been requested")]
+    PauseAlreadyRequested,
+    #[msg("No pause has been requested")]
+    PauseNotRequested,
+    #[msg("Challenge is not paused")]
+    ChallengeNotPaused,
+    #[msg("Start time must be between now and the configured horizon")]
+    InvalidStartTime,
+    #[msg("Challenge has not started yet")]
+    ChallengeNotStarted,
+    #[msg("Challenge has already started")]
+    ChallengeAlreadyStarted,
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+    #[msg("Challenge is not awaiting renewal")]
+    NotRenewable,
+    #[msg("Renewal allowance does not cover the next deposit")]
+    RenewalAllowanceExhausted,
+    #[msg("Renewal is pending; renew the challenge or release the rollover first")]
+    RenewalPending,
+    #[msg("Phase targets must be positive, fit the duration and add up to the total sessions")]
+    InvalidPhasePlan,
+    #[msg("The current phase has ended and must be settled first")]
+    PhaseSettlementDue,
+    #[msg("Only the last phase remains; finalize the challenge instead")]
+    NoPhaseToSettle,
+    #[msg("The current phase has not ended yet")]
+    PhaseNotOver,
+    #[msg("Milestone refunds are not enabled for this challenge")]
+    MilestonesDisabled,
+    #[msg("No milestone refund is available to claim")]
+    NoMilestoneRefund,
+    #[msg("Milestone step must be 0 or between the protocol minimum and 10000 basis points")]
+    InvalidMilestoneStep,
+    #[msg("Invalid challenge template")]
+    InvalidTemplate,
+    #[msg("Challenge template is not accepting new participants")]
+    TemplateInactive,
+    #[msg("Verifier is not in the template's verifier set")]
+    VerifierNotInTemplate,
+    #[msg("Template account does not match the challenge")]
+    TemplateMismatch,
+    #[msg("Participants cannot pledge to their own challenge")]
+    CannotPledgeOwnChallenge,
+    #[msg("Challenge was not cancelled")]
+    ChallengeNotCancelled,
+    #[msg("Pledges must be refunded before the challenge is closed")]
+    PledgesOutstanding,
+    #[msg("Maximum pause duration must be at least one day")]
+    InvalidPauseDuration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metadata::{FitnessActivity, SessionDetails, MAX_COURSE_ID_LEN, MAX_LOCATION_LEN, MAX_NOTES_LEN};
+    use proof::MAX_PROOF_URL_LEN;
+
+    fn assert_fits<T: AnchorSerialize + Space>(account: &T) {
+        assert_eq!(account.try_to_vec().unwrap().len(), T::INIT_SPACE);
+    }
+
+    fn largest_metadata() -> SessionMetadata {
+        SessionMetadata {
+            duration_minutes: Some(u16::MAX),
+            location: Some("l".repeat(MAX_LOCATION_LEN)),
+            notes: Some("n".repeat(MAX_NOTES_LEN)),
+            details: SessionDetails::Education {
+                pages: u16::MAX,
+                course_id: Some("c".repeat(MAX_COURSE_ID_LEN)),
+                quiz_score: Some(100),
+            },
+        }
+    }
+
+    fn largest_challenge() -> Challenge {
+        Challenge {
+            participant: Pubkey::new_unique(),
+            deposit_amount: u64::MAX,
+            total_sessions: u32::MAX,
+            completed_sessions: u32::MAX,
+            sessions_recorded: u32::MAX,
+            start_time: i64::MAX,
+            end_time: i64::MAX,
+            last_session_time: i64::MAX,
+            status: ChallengeStatus::Active,
+            verifier: Some(Pubkey::new_unique()),
+            challenge_id: u64::MAX,
+            challenge_type_id: u16::MAX,
+            schema: MetadataSchema::Fitness,
+            min_session_minutes: u16::MAX,
+            session_goal: SessionGoal::Fitness {
+                min_steps: Some(u32::MAX),
+                min_distance_m: Some(u32::MAX),
+                activity: Some(FitnessActivity::Run),
+            },
+            schedule: Schedule::Weekdays { mask: 0x7f, utc_offset_minutes: i16::MAX },
+            recent_session_times: [i64::MAX; RECENT_SESSIONS],
+            grace_periods_used: u8::MAX,
+            max_grace_periods: u8::MAX,
+            grace_periods_purchased: u8::MAX,
+            grace_period_days: u16::MAX,
+            grace_refund_penalty_bps: u16::MAX,
+            pause_requested_at: i64::MAX,
+            paused_at: i64::MAX,
+            total_paused_seconds: i64::MAX,
+            duration_days: u32::MAX,
+            recurring: true,
+            rollover_amount: u64::MAX,
+            phase_targets: vec![u32::MAX; MAX_PHASES],
+            phase_index: u8::MAX,
+            phase_completed: u32::MAX,
+            refunded_so_far: u64::MAX,
+            penalized_so_far: u64::MAX,
+            milestone_step_bps: u16::MAX,
+            milestones_passed: u8::MAX,
+            milestone_excluded_deposit: u64::MAX,
+            template: Some(Pubkey::new_unique()),
+            pledged_total: u64::MAX,
+            pledge_count: u32::MAX,
+            finalized_at: i64::MAX,
+            session_root: [0xff; 32],
+            compressed_sessions: u32::MAX,
+        }
+    }
+
+    #[test]
+    fn config_space() {
+        assert_fits(&Config {
+            authority: Pubkey::new_unique(),
+            treasury: Pubkey::new_unique(),
+            accepted_mint: Pubkey::new_unique(),
+            fee_percentage: 20,
+            reward_percentage: 70,
+            charity_percentage: 10,
+            total_challenges: u64::MAX,
+            total_volume: u64::MAX,
+            paused: false,
+            min_deposit: u64::MAX,
+            max_deposit: u64::MAX,
+            arbiter: Pubkey::new_unique(),
+            grace_price_base: u64::MAX,
+            grace_price_increment: u64::MAX,
+            max_grace_purchases: u8::MAX,
+            max_start_delay_days: u16::MAX,
+            min_milestone_step_bps: u16::MAX,
+            max_pause_days: u16::MAX,
+        });
+    }
+
+    #[test]
+    fn challenge_space() {
+        assert_fits(&largest_challenge());
+    }
+
+    #[test]
+    fn phases_split_time_and_deposit() {
+        let mut challenge = largest_challenge();
+        challenge.deposit_amount = 10_000_001;
+        challenge.total_sessions = 12;
+        challenge.phase_targets = vec![2, 4, 6];
+        challenge.phase_index = 0;
+        challenge.refunded_so_far = 0;
+        challenge.penalized_so_far = 0;
+        challenge.start_time = 0;
+        challenge.end_time = 21 * math::SECONDS_PER_DAY;
+
+        let mut settled = 0;
+        for (phase, end_day) in [(0, 7), (1, 14), (2, 21)] {
+            challenge.phase_index = phase;
+            assert_eq!(challenge.phase_end().unwrap(), end_day * math::SECONDS_PER_DAY);
+            let deposit = challenge.phase_deposit().unwrap();
+            settled += deposit;
+            // Alternate refund and penalty to exercise both counters
+            if phase % 2 == 0 {
+                challenge.refunded_so_far += deposit;
+            } else {
+                challenge.penalized_so_far += deposit;
+            }
+        }
+        assert!(challenge.is_last_phase());
+        // The last phase absorbs rounding so the whole deposit is settled exactly once
+        assert_eq!(settled, 10_000_001);
+
+        // A span that would wrap is an error rather than a far-future boundary
+        challenge.end_time = -1;
+        assert!(challenge.phase_end().is_err());
+        challenge.start_time = i64::MIN;
+        challenge.end_time = i64::MAX;
+        assert!(challenge.phase_end().is_err());
+    }
+
+    #[test]
+    fn phase_settlement_applies_grace_penalties() {
+        let mut challenge = largest_challenge();
+        challenge.deposit_amount = 10_000_000;
+        challenge.total_sessions = 8;
+        challenge.phase_targets = vec![4, 4];
+        challenge.phase_index = 0;
+        challenge.phase_completed = 3;
+        challenge.grace_periods_used = 0;
+        challenge.grace_periods_purchased = 0;
+        challenge.grace_refund_penalty_bps = 1000;
+        assert_eq!(challenge.phase_settlement().unwrap(), (3_750_000, 1_250_000));
+
+        // One free grace period costs 10% of the earned refund
+        challenge.grace_periods_used = 1;
+        assert_eq!(challenge.phase_settlement().unwrap(), (3_375_000, 1_625_000));
+        // A purchased one is already paid for
+        challenge.grace_periods_purchased = 1;
+        assert_eq!(challenge.phase_settlement().unwrap(), (3_750_000, 1_250_000));
+    }
+
+    #[test]
+    fn milestone_refunds_leave_the_remainder_for_finalize() {
+        let mut challenge = largest_challenge();
+        challenge.deposit_amount = 1_000_003;
+        challenge.total_sessions = 8;
+        challenge.phase_targets = vec![8];
+        challenge.phase_index = 0;
+        challenge.milestone_step_bps = 2500;
+        challenge.milestone_excluded_deposit = 0;
+        challenge.grace_periods_used = 1;
+        challenge.grace_periods_purchased = 0;
+        challenge.grace_refund_penalty_bps = 1000;
+        challenge.refunded_so_far = 0;
+
+        challenge.completed_sessions = 1;
+        assert_eq!(challenge.milestones_reached().unwrap(), 0);
+        assert_eq!(challenge.milestone_refund_unlocked().unwrap(), 0);
+
+        challenge.completed_sessions = 5;
+        assert_eq!(challenge.milestones_reached().unwrap(), 2);
+        // Half the deposit, less the 10% grace penalty
+        assert_eq!(challenge.milestone_refund_unlocked().unwrap(), 450_000);
+        challenge.refunded_so_far = challenge.milestone_refund_unlocked().unwrap();
+
+        // Finalize pays what is left of the earned refund and penalizes the rest
+        challenge.phase_completed = 6;
+        let (stake, already_refunded) = challenge.last_phase_stake().unwrap();
+        let earned = math::mul_div(stake, 6, 8).unwrap();
+        let earned = math::mul_div(earned, challenge.grace_refund_factor_bps(), math::BASIS_POINTS).unwrap();
+        let refund = earned.saturating_sub(already_refunded);
+        let penalty = stake - already_refunded - refund;
+        assert_eq!(already_refunded + refund + penalty, 1_000_003);
+
+        challenge.milestone_step_bps = 0;
+        assert_eq!(challenge.milestones_reached().unwrap(), 0);
+    }
+
+    #[test]
+    fn top_ups_only_count_toward_later_milestones() {
+        let mut challenge = largest_challenge();
+        challenge.deposit_amount = 1_000_000;
+        challenge.total_sessions = 4;
+        challenge.phase_targets = vec![4];
+        challenge.milestone_step_bps = 2500;
+        challenge.grace_periods_used = 0;
+        challenge.grace_periods_purchased = 0;
+        challenge.milestone_excluded_deposit = 0;
+
+        challenge.completed_sessions = 2;
+        challenge.milestones_passed = 2;
+        assert_eq!(challenge.milestone_refund_unlocked().unwrap(), 500_000);
+
+        // Doubling the deposit after two milestones unlocks nothing new yet
+        challenge.deposit_amount = 2_000_000;
+        challenge.exclude_from_passed_milestones(1_000_000).unwrap();
+        assert_eq!(challenge.milestone_refund_unlocked().unwrap(), 500_000);
+
+        // The next milestone's slice comes from the larger deposit
+        challenge.completed_sessions = 3;
+        challenge.milestones_passed = 3;
+        assert_eq!(challenge.milestone_refund_unlocked().unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn milestone_steps_are_validated() {
+        let mut config = protocol_config();
+        assert!(validate_milestone_step(&config, &[12], 0).is_ok());
+        assert!(validate_milestone_step(&config, &[12], 2500).is_ok());
+        assert!(validate_milestone_step(&config, &[12], 10_000).is_ok());
+        for step in [999, 10_001] {
+            assert_eq!(
+                validate_milestone_step(&config, &[12], step).unwrap_err(),
+                ErrorCode::InvalidMilestoneStep.into()
+            );
+        }
+        // Phased challenges settle each phase instead
+        assert_eq!(
+            validate_milestone_step(&config, &[6, 6], 2500).unwrap_err(),
+            ErrorCode::InvalidMilestoneStep.into()
+        );
+        // Steps below 40 bps would need more than 255 milestones
+        config.min_milestone_step_bps = 1;
+        assert!(validate_milestone_step(&config, &[12], 39).is_err());
+        assert!(validate_milestone_step(&config, &[12], 40).is_ok());
+
+        config.min_milestone_step_bps = 0;
+        assert_eq!(
+            validate_milestone_step(&config, &[12], 2500).unwrap_err(),
+            ErrorCode::MilestonesDisabled.into()
+        );
+        assert!(validate_milestone_step(&config, &[12], 0).is_ok());
+    }
+
+    #[test]
+    fn phase_plans_are_validated() {
+        assert!(validate_phase_targets(&[7, 7, 7, 7], 28, 28).is_ok());
+        assert!(validate_phase_targets(&[28], 28, 28).is_ok());
+        assert!(validate_phase_targets(&[], 28, 28).is_err());
+        assert!(validate_phase_targets(&[7, 7, 7, 6], 28, 28).is_err());
+        assert!(validate_phase_targets(&[14, 0, 14], 28, 28).is_err());
+        assert!(validate_phase_targets(&[1; 8], 8, 7).is_err()); // More phases than days
+        assert!(validate_phase_targets(&[1; MAX_PHASES + 1], MAX_PHASES as u32 + 1, 365).is_err());
+    }
+
+    #[test]
+    fn next_run_keeps_terms_and_resets_progress() {
+        let mut previous = largest_challenge();
+        previous.status = ChallengeStatus::Completed;
+        previous.duration_days = 30;
+        let now = 1_704_067_200;
+
+        let next = previous.next_run(7, now).unwrap();
+        assert_eq!(next.challenge_id, 7);
+        assert_eq!((next.start_time, next.end_time), (now, now + 30 * math::SECONDS_PER_DAY));
+        assert!(next.status == ChallengeStatus::Active);
+        assert_eq!((next.completed_sessions, next.sessions_recorded, next.grace_periods_used), (0, 0, 0));
+        assert_eq!((next.finalized_at, next.rollover_amount, next.total_paused_seconds), (0, 0, 0));
+        assert_eq!(next.session_root, merkle::empty_root());
+        assert!(next.recurring);
+        // Terms carry over unchanged
+        assert_eq!(next.participant, previous.participant);
+        assert_eq!(next.deposit_amount, previous.deposit_amount);
+        assert_eq!(next.schedule, previous.schedule);
+        assert_eq!(next.verifier, previous.verifier);
+        assert_eq!(next.max_grace_periods, previous.max_grace_periods);
+    }
+
+    #[test]
+    fn pauses_are_capped_at_the_maximum() {
+        let mut challenge = largest_challenge();
+        challenge.paused_at = 1_000;
+        let day = math::days_to_seconds(1);
+        assert_eq!(challenge.pause_credit(1_000 + day / 2, day).unwrap(), (day / 2, false));
+        assert_eq!(challenge.pause_credit(1_000 + day, day).unwrap(), (day, true));
+        assert_eq!(challenge.pause_credit(1_000 + 3 * day, day).unwrap(), (day, true));
+    }
+
+    #[test]
+    fn session_space() {
+        assert_fits(&Session {
+            challenge: Pubkey::new_unique(),
+            session_number: u32::MAX,
+            timestamp: i64::MAX,
+            phase_index: u8::MAX,
+            proof: ProofRef::UrlWithDigest {
+                url: "u".repeat(MAX_PROOF_URL_LEN),
+                sha256: [0xff; 32],
+            },
+            verified_by: Pubkey::new_unique(),
+            metadata: largest_metadata(),
+            auto_verified: false,
+            revoked: false,
+            rent_payer: Pubkey::new_unique(),
+        });
+    }
+
+    #[test]
+    fn session_tree_space() {
+        assert_fits(&SessionTree {
+            challenge: Pubkey::new_unique(),
+            rent_payer: Pubkey::new_unique(),
+            frontier: [[0xff; 32]; SESSION_TREE_DEPTH],
+        });
+    }
+
+    #[test]
+    fn user_stats_space() {
+        assert_fits(&UserStats {
+            user: Pubkey::new_unique(),
+            total_challenges: u32::MAX,
+            challenges_completed: u32::MAX,
+            challenges_partial: u32::MAX,
+            challenges_failed: u32::MAX,
+            perfect_completions: u32::MAX,
+            total_sessions_completed: u32::MAX,
+            total_deposited: u64::MAX,
+            total_refunded: u64::MAX,
+            total_penalties: u64::MAX,
+            total_rewards_claimed: u64::MAX,
+            current_streak: u32::MAX,
+            best_streak: u32::MAX,
+            last_activity: i64::MAX,
+            last_claim_epoch: u64::MAX,
+            total_grace_periods_used: u32::MAX,
+            total_sessions_revoked: u32::MAX,
+            challenges_closed: u32::MAX,
+            challenges_created: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn stats_shard_space() {
+        assert_fits(&StatsShard {
+            shard_index: STATS_SHARD_COUNT - 1,
+            challenges_created: u64::MAX,
+            volume: u64::MAX,
+            sessions_completed: u64::MAX,
+            refunds: u64::MAX,
+            penalties: u64::MAX,
+            fees: u64::MAX,
+            rewards_claimed: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn aggregates_stats_shards_without_overflow() {
+        let shard = |shard_index: u8, volume: u64| StatsShard {
+            shard_index,
+            challenges_created: 2,
+            volume,
+            sessions_completed: 10,
+            refunds: volume / 2,
+            penalties: volume / 2,
+            fees: 1,
+            rewards_claimed: 0,
+        };
+        let shards: Vec<StatsShard> = (0..STATS_SHARD_COUNT).map(|i| shard(i, u64::MAX)).collect();
+        let total = aggregate_stats(&shards);
+        assert_eq!(total.challenges_created, 2 * STATS_SHARD_COUNT as u64);
+        assert_eq!(total.volume, u64::MAX as u128 * STATS_SHARD_COUNT as u128);
+        assert_eq!(total.sessions_completed, 10 * STATS_SHARD_COUNT as u64);
+        assert_eq!(total.fees, STATS_SHARD_COUNT as u128);
+        assert_eq!(aggregate_stats(&[]), ProtocolStats::default());
+    }
+
+    #[test]
+    fn stats_shard_index_stays_in_range() {
+        for _ in 0..64 {
+            assert!(stats_shard_index(&Pubkey::new_unique()) < STATS_SHARD_COUNT);
+        }
+    }
+
+    #[test]
+    fn finalization_record_space() {
+        assert_fits(&FinalizationRecord {
+            challenge: Pubkey::new_unique(),
+            participant: Pubkey::new_unique(),
+            completion_rate_percentage: u64::MAX,
+            penalty_amount: u64::MAX,
+            reward_pool_contribution: u64::MAX,
+            timestamp: i64::MAX,
+            rewarded: true,
+            rent_payer: Pubkey::new_unique(),
+        });
+    }
+
+    #[test]
+    fn reward_state_space() {
+        assert_fits(&RewardState {
+            last_epoch_processed: u64::MAX,
+            next_epoch_time: i64::MAX,
+            total_distributed: u64::MAX,
+            last_distribution_time: i64::MAX,
+        });
+    }
+
+    #[test]
+    fn grace_period_record_space() {
+        assert_fits(&GracePeriodRecord {
+            challenge: Pubkey::new_unique(),
+            participant: Pubkey::new_unique(),
+            used_at: i64::MAX,
+            reason: "r".repeat(MAX_GRACE_REASON_LEN),
+            new_end_time: i64::MAX,
+        });
+    }
+
+    #[test]
+    fn challenge_type_config_space() {
+        assert_fits(&ChallengeTypeConfig {
+            type_id: u16::MAX,
+            name: "n".repeat(MAX_CHALLENGE_TYPE_NAME_LEN),
+            schema: MetadataSchema::Custom,
+            min_session_minutes: u16::MAX,
+            min_deposit: u64::MAX,
+            max_deposit: u64::MAX,
+            default_grace_periods: u8::MAX,
+            max_grace_periods: u8::MAX,
+            default_grace_days: u16::MAX,
+            max_grace_days: u16::MAX,
+            grace_refund_penalty_bps: u16::MAX,
+            min_sessions: u32::MAX,
+            max_sessions: u32::MAX,
+            min_duration_days: u32::MAX,
+            max_duration_days: u32::MAX,
+            active: true,
+        });
+    }
+
+    #[test]
+    fn attester_record_space() {
+        assert_fits(&AttesterRecord {
+            attester: Pubkey::new_unique(),
+            registered_at: i64::MAX,
+            sessions_attested: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn pledge_space() {
+        assert_fits(&Pledge {
+            challenge: Pubkey::new_unique(),
+            supporter: Pubkey::new_unique(),
+            amount: u64::MAX,
+            pledged_at: i64::MAX,
+        });
+    }
+
+    #[test]
+    fn coach_record_space() {
+        assert_fits(&CoachRecord {
+            coach: Pubkey::new_unique(),
+            registered_at: i64::MAX,
+        });
+    }
+
+    #[test]
+    fn challenge_template_space() {
+        assert_fits(&ChallengeTemplate {
+            creator: Pubkey::new_unique(),
+            template_id: u64::MAX,
+            name: "n".repeat(MAX_TEMPLATE_NAME_LEN),
+            challenge_type_id: u16::MAX,
+            total_sessions: u32::MAX,
+            duration_days: u32::MAX,
+            session_goal: SessionGoal::Fitness {
+                min_steps: Some(u32::MAX),
+                min_distance_m: Some(u32::MAX),
+                activity: Some(FitnessActivity::Run),
+            },
+            schedule: Some(Schedule::Weekdays { mask: 0x7f, utc_offset_minutes: i16::MAX }),
+            grace_terms: Some(GraceTerms { periods: u8::MAX, days: u16::MAX }),
+            grace_refund_penalty_bps: Some(u16::MAX),
+            phase_targets: vec![u32::MAX; MAX_PHASES],
+            verifiers: vec![Pubkey::new_unique(); MAX_TEMPLATE_VERIFIERS],
+            min_deposit: u64::MAX,
+            max_deposit: u64::MAX,
+            times_used: u64::MAX,
+            runs_completed: u64::MAX,
+            active: true,
+        });
+    }
+
+    #[test]
+    fn oversize_free_form_fields_are_rejected() {
+        let validate = |metadata: &SessionMetadata| {
+            validate_session_metadata(&MetadataSchema::Custom, 0, &SessionGoal::None, metadata)
+        };
+        assert!(validate(&largest_metadata()).is_ok());
+
+        let mut metadata = largest_metadata();
+        metadata.notes = Some("n".repeat(MAX_NOTES_LEN + 1));
+        assert_eq!(validate(&metadata).unwrap_err(), ErrorCode::NotesTooLong.into());
+
+        let mut metadata = largest_metadata();
+        metadata.location = Some("l".repeat(MAX_LOCATION_LEN + 1));
+        assert_eq!(validate(&metadata).unwrap_err(), ErrorCode::LocationTooLong.into());
+    }
+
+    fn protocol_config() -> Config {
+        Config {
+            authority: Pubkey::default(),
+            treasury: Pubkey::default(),
+            accepted_mint: Pubkey::default(),
+            fee_percentage: 5,
+            reward_percentage: 20,
+            charity_percentage: 75,
+            total_challenges: 0,
+            total_volume: 0,
+            paused: false,
+            min_deposit: 5_000_000,
+            max_deposit: 10_000_000_000,
+            arbiter: Pubkey::default(),
+            grace_price_base: 1_000_000,
+            grace_price_increment: 1_000_000,
+            max_grace_purchases: 3,
+            max_start_delay_days: 14,
+            min_milestone_step_bps: 1000,
+            max_pause_days: 30,
+        }
+    }
+
+    #[test]
+    fn grace_purchase_price_escalates() {
+        let mut config = protocol_config();
+        config.grace_price_increment = 500_000;
+        let prices: Vec<u64> = (0..3).map(|n| grace_purchase_price(&config, n).unwrap()).collect();
+        assert_eq!(prices, [1_000_000, 1_500_000, 2_000_000]);
+
+        config.grace_price_increment = u64::MAX;
+        assert!(grace_purchase_price(&config, 1).is_err());
+    }
+
+    fn fitness_type_params() -> ChallengeTypeParams {
+        ChallengeTypeParams {
+            name: "Fitness".to_string(),
+            schema: MetadataSchema::Fitness,
+            min_session_minutes: 20,
+            min_deposit: 5_000_000,
+            max_deposit: 10_000_000_000,
+            default_grace_periods: 3,
+            max_grace_periods: 5,
+            default_grace_days: 3,
+            max_grace_days: 7,
+            grace_refund_penalty_bps: 500,
+            min_sessions: 1,
+            max_sessions: 365,
+            min_duration_days: 7,
+            max_duration_days: 365,
+            active: true,
+        }
+    }
+
+    #[test]
+    fn challenge_type_grace_limits_are_validated() {
+        let config = protocol_config();
+        let params = fitness_type_params();
+        assert!(validate_challenge_type_params(&config, &params).is_ok());
+
+        let invalid = [
+            ChallengeTypeParams { default_grace_periods: 6, ..params.clone() },
+            ChallengeTypeParams { default_grace_days: 0, ..params.clone() },
+            ChallengeTypeParams { default_grace_days: 8, ..params.clone() },
+            ChallengeTypeParams { max_grace_days: MAX_GRACE_DAYS + 1, ..params.clone() },
+            // 5 periods at 25% each would take more than the whole refund
+            ChallengeTypeParams { grace_refund_penalty_bps: 2_500, ..params.clone() },
+        ];
+        for params in &invalid {
+            assert_eq!(
+                validate_challenge_type_params(&config, params).unwrap_err(),
+                ErrorCode::InvalidChallengeTypeConfig.into()
+            );
+        }
+    }
+
+    fn fitness_type() -> ChallengeTypeConfig {
+        let mut challenge_type = ChallengeTypeConfig {
+            type_id: 1,
+            name: String::new(),
+            schema: MetadataSchema::Custom,
+            min_session_minutes: 0,
+            min_deposit: 0,
+            max_deposit: 0,
+            default_grace_periods: 0,
+            max_grace_periods: 0,
+            default_grace_days: 0,
+            max_grace_days: 0,
+            grace_refund_penalty_bps: 0,
+            min_sessions: 0,
+            max_sessions: 0,
+            min_duration_days: 0,
+            max_duration_days: 0,
+            active: false,
+        };
+        challenge_type.apply(fitness_type_params());
+        challenge_type
+    }
+
+    #[test]
+    fn stakes_follow_the_current_limits() {
+        let mut config = protocol_config();
+        let mut challenge_type = fitness_type();
+        assert!(validate_stake(&config, &challenge_type, 5_000_000).is_ok());
+
+        // A renewal repeats these checks, so tightened limits stop it
+        config.min_deposit = 10_000_000;
+        assert_eq!(
+            validate_stake(&config, &challenge_type, 5_000_000).unwrap_err(),
+            ErrorCode::DepositTooSmall.into()
+        );
+        config.min_deposit = 5_000_000;
+        challenge_type.max_deposit = 1_000_000_000;
+        assert_eq!(
+            validate_stake(&config, &challenge_type, 2_000_000_000).unwrap_err(),
+            ErrorCode::DepositTooLarge.into()
+        );
+        challenge_type.active = false;
+        assert_eq!(
+            validate_stake(&config, &challenge_type, 5_000_000).unwrap_err(),
+            ErrorCode::ChallengeTypeInactive.into()
+        );
+    }
+
+    #[test]
+    fn template_terms_are_validated() {
+        let config = protocol_config();
+        let mut challenge_type = fitness_type();
+        let params = ChallengeTemplateParams {
+            name: "30-day couch to 5K".to_string(),
+            challenge_type_id: 1,
+            total_sessions: 12,
+            duration_days: 30,
+            session_goal: SessionGoal::None,
+            schedule: Some(Schedule::PerWeek { sessions: 3 }),
+            grace_terms: Some(GraceTerms { periods: 1, days: 3 }),
+            grace_refund_penalty_bps: Some(2_000),
+            phase_targets: vec![3, 3, 3, 3],
+            verifiers: vec![Pubkey::new_unique(); 2],
+            min_deposit: 10_000_000,
+            max_deposit: 100_000_000,
+        };
+        assert!(validate_template_params(&config, &challenge_type, &params).is_ok());
+        // An empty plan is a single phase
+        let single_phase = ChallengeTemplateParams { phase_targets: vec![], ..params.clone() };
+        assert!(validate_template_params(&config, &challenge_type, &single_phase).is_ok());
+
+        let invalid = [
+            (ChallengeTemplateParams { name: String::new(), ..params.clone() }, ErrorCode::InvalidTemplate),
+            (ChallengeTemplateParams { min_deposit: 1_000_000, ..params.clone() }, ErrorCode::InvalidTemplate),
+            (ChallengeTemplateParams { max_deposit: 5_000_000, ..params.clone() }, ErrorCode::InvalidTemplate),
+            (
+                ChallengeTemplateParams { verifiers: vec![Pubkey::new_unique(); MAX_TEMPLATE_VERIFIERS + 1], ..params.clone() },
+                ErrorCode::InvalidTemplate,
+            ),
+            (ChallengeTemplateParams { total_sessions: 366, ..params.clone() }, ErrorCode::InvalidSessionCount),
+            (ChallengeTemplateParams { phase_targets: vec![6, 5], ..params.clone() }, ErrorCode::InvalidPhasePlan),
+            (
+                ChallengeTemplateParams { grace_terms: Some(GraceTerms { periods: 1, days: 8 }), ..params.clone() },
+                ErrorCode::InvalidGraceTerms,
+            ),
+            // Five grace periods at 20.01% each would forfeit more than the refund
+            (
+                ChallengeTemplateParams { grace_refund_penalty_bps: Some(2_001), ..params.clone() },
+                ErrorCode::InvalidGraceTerms,
+            ),
+        ];
+        for (params, error) in invalid {
+            assert_eq!(validate_template_params(&config, &challenge_type, &params).unwrap_err(), error.into());
+        }
+
+        challenge_type.active = false;
+        assert_eq!(
+            validate_template_params(&config, &challenge_type, &params).unwrap_err(),
+            ErrorCode::ChallengeTypeInactive.into()
+        );
+    }
+}
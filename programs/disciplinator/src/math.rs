@@ -0,0 +1,160 @@
+//! Checked integer arithmetic for every time and token amount calculation.
+//!
+//! Floating point is both expensive in BPF compute units and prone to
+//! platform-dependent truncation, so all ratios are computed on widened
+//! integers and every overflow surfaces as a program error.
+
+use anchor_lang::prelude::*;
+
+use crate::ErrorCode;
+
+pub const SECONDS_PER_HOUR: i64 = 3600;
+pub const SECONDS_PER_DAY: i64 = 24 * SECONDS_PER_HOUR;
+
+/// Completion rates are expressed in basis points: 10000 = 100.00%.
+pub const BASIS_POINTS: u64 = 10_000;
+
+const MIN_INTERVAL_HOURS: u64 = 12;
+const MAX_INTERVAL_HOURS: u64 = 48;
+
+pub fn hours_to_seconds(hours: u16) -> i64 {
+    hours as i64 * SECONDS_PER_HOUR // u16::MAX hours cannot overflow i64
+}
+
+pub fn days_to_seconds(days: u32) -> i64 {
+    days as i64 * SECONDS_PER_DAY // u32::MAX days cannot overflow i64
+}
+
+/// `timestamp + seconds`, failing with `TimeOverflow`.
+pub fn add_seconds(timestamp: i64, seconds: i64) -> Result<i64> {
+    timestamp.checked_add(seconds).ok_or_else(|| error!(ErrorCode::TimeOverflow))
+}
+
+/// `timestamp - seconds`, failing with `TimeOverflow`.
+pub fn sub_seconds(timestamp: i64, seconds: i64) -> Result<i64> {
+    timestamp.checked_sub(seconds).ok_or_else(|| error!(ErrorCode::TimeOverflow))
+}
+
+/// `amount * numerator / denominator`, rounded down, computed in u128.
+pub fn mul_div(amount: u64, numerator: u64, denominator: u64) -> Result<u64> {
+    require!(denominator > 0, ErrorCode::ArithmeticOverflow);
+    let result = amount as u128 * numerator as u128 / denominator as u128;
+    u64::try_from(result).map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// `percent`% of `amount`, rounded down.
+pub fn percentage_of(amount: u64, percent: u8) -> Result<u64> {
+    mul_div(amount, percent as u64, 100)
+}
+
+/// `part / whole` in basis points, rounded down.
+pub fn basis_points(part: u32, whole: u32) -> Result<u64> {
+    mul_div(part as u64, BASIS_POINTS, whole as u64)
+}
+
+pub fn checked_add(a: u64, b: u64) -> Result<u64> {
+    a.checked_add(b).ok_or_else(|| error!(ErrorCode::ArithmeticOverflow))
+}
+
+pub fn checked_sub(a: u64, b: u64) -> Result<u64> {
+    a.checked_sub(b).ok_or_else(|| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Bumps a counter by one, failing instead of wrapping.
+pub fn increment<T: Copy + Into<u64> + TryFrom<u64>>(counter: &mut T) -> Result<()> {
+    let next = checked_add((*counter).into(), 1)?;
+    *counter = T::try_from(next).map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
+    Ok(())
+}
+
+/// Default gap between sessions: the challenge duration spread evenly over
+/// its sessions, clamped to 12-48 hours.
+pub fn minimum_interval_hours(total_sessions: u32, duration_days: u32) -> u16 {
+    let total_hours = duration_days as u64 * 24;
+    let interval = total_hours
+        .checked_div(total_sessions as u64)
+        .unwrap_or(MAX_INTERVAL_HOURS);
+    interval.clamp(MIN_INTERVAL_HOURS, MAX_INTERVAL_HOURS) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// The floating point formula this module replaced, kept as the reference.
+    fn reference_minimum_interval(total_sessions: u32, duration_days: u32) -> u16 {
+        let total_hours = duration_days as f64 * 24.0;
+        let interval = total_hours / total_sessions as f64;
+        interval.clamp(12.0, 48.0) as u16
+    }
+
+    /// Schoolbook long division over decimal digits, a different algorithm
+    /// from the widened multiply it checks.
+    fn reference_mul_div(amount: u64, numerator: u64, denominator: u64) -> Option<u64> {
+        let (quotient, remainder) = (amount / denominator, amount % denominator);
+        let whole = quotient.checked_mul(numerator)?;
+        // remainder * numerator / denominator without overflowing
+        let (mut result, mut carry) = (0u64, 0u128);
+        for digit in (remainder as u128 * numerator as u128).to_string().bytes() {
+            carry = carry * 10 + (digit - b'0') as u128;
+            result = result.checked_mul(10)?.checked_add((carry / denominator as u128) as u64)?;
+            carry %= denominator as u128;
+        }
+        whole.checked_add(result)
+    }
+
+    #[test]
+    fn known_intervals() {
+        assert_eq!(minimum_interval_hours(30, 30), 24);
+        assert_eq!(minimum_interval_hours(365, 365), 24);
+        assert_eq!(minimum_interval_hours(100, 7), 12);
+        assert_eq!(minimum_interval_hours(1, 365), 48);
+        assert_eq!(minimum_interval_hours(31, 30), 23);
+        assert_eq!(minimum_interval_hours(0, 30), 48);
+    }
+
+    #[test]
+    fn known_ratios() {
+        assert_eq!(percentage_of(1_000_000, 20).unwrap(), 200_000);
+        assert_eq!(percentage_of(u64::MAX, 100).unwrap(), u64::MAX);
+        assert_eq!(basis_points(8, 10).unwrap(), 8000);
+        assert_eq!(basis_points(1, 3).unwrap(), 3333);
+        assert!(mul_div(1, 1, 0).is_err());
+        assert!(mul_div(u64::MAX, 2, 1).is_err());
+        assert!(add_seconds(i64::MAX, 1).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn interval_matches_float_reference(sessions in 1u32..=1000, days in 0u32..=1000) {
+            prop_assert_eq!(minimum_interval_hours(sessions, days), reference_minimum_interval(sessions, days));
+        }
+
+        #[test]
+        fn mul_div_matches_reference(amount: u64, numerator: u64, denominator in 1u64..) {
+            prop_assert_eq!(
+                mul_div(amount, numerator, denominator).ok(),
+                reference_mul_div(amount, numerator, denominator)
+            );
+        }
+
+        #[test]
+        fn refund_and_penalty_conserve_the_deposit(deposit: u64, total in 1u32..=365, completed_seed: u32) {
+            let completed = completed_seed % (total + 1);
+            let refund = mul_div(deposit, completed as u64, total as u64).unwrap();
+            let penalty = checked_sub(deposit, refund).unwrap();
+            prop_assert_eq!(refund + penalty, deposit);
+            prop_assert!(basis_points(completed, total).unwrap() <= BASIS_POINTS);
+        }
+
+        #[test]
+        fn penalty_split_conserves_the_penalty(penalty: u64, fee in 0u8..=100, reward_seed: u8) {
+            let reward = reward_seed % (101 - fee);
+            let protocol_fee = percentage_of(penalty, fee).unwrap();
+            let reward_pool = percentage_of(penalty, reward).unwrap();
+            let charity = checked_sub(checked_sub(penalty, protocol_fee).unwrap(), reward_pool).unwrap();
+            prop_assert_eq!(protocol_fee as u128 + reward_pool as u128 + charity as u128, penalty as u128);
+        }
+    }
+}
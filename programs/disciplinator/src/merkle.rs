@@ -0,0 +1,119 @@
+//! Append-only Merkle accumulator for compressed session history.
+//!
+//! Only the right-most path ("frontier") of a fixed-depth tree is kept
+//! on-chain, which is enough to append leaves and recompute the root. Leaves
+//! themselves live in `SessionAppended` events and can be replayed with
+//! [`root_from_leaves`] to rebuild the tree and produce inclusion proofs.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+
+use crate::ErrorCode;
+
+/// 2^9 = 512 leaves, enough for the 365-session maximum.
+pub const SESSION_TREE_DEPTH: usize = 9;
+pub const SESSION_TREE_CAPACITY: u32 = 1 << SESSION_TREE_DEPTH;
+
+// Domain separation keeps a leaf from ever being read as an inner node
+const LEAF_PREFIX: &[u8] = &[0x00];
+const NODE_PREFIX: &[u8] = &[0x01];
+
+pub fn hash_leaf(data: &[u8]) -> [u8; 32] {
+    hashv(&[LEAF_PREFIX, data]).to_bytes()
+}
+
+pub fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hashv(&[NODE_PREFIX, left, right]).to_bytes()
+}
+
+/// Root of a tree whose leaves are all empty (zeroed).
+pub fn empty_root() -> [u8; 32] {
+    let mut node = [0u8; 32];
+    for _ in 0..SESSION_TREE_DEPTH {
+        node = hash_nodes(&node, &node);
+    }
+    node
+}
+
+/// Appends `leaf` at index `count`, updating `frontier` in place. Returns
+/// the new root.
+pub fn append(frontier: &mut [[u8; 32]; SESSION_TREE_DEPTH], count: u32, leaf: [u8; 32]) -> Result<[u8; 32]> {
+    require!(count < SESSION_TREE_CAPACITY, ErrorCode::SessionTreeFull);
+
+    let mut index = count;
+    let mut node = leaf;
+    let mut zero = [0u8; 32];
+    for subtree in frontier.iter_mut() {
+        node = if index & 1 == 0 {
+            // Left child: remember it for the sibling that arrives later
+            *subtree = node;
+            hash_nodes(&node, &zero)
+        } else {
+            hash_nodes(subtree, &node)
+        };
+        zero = hash_nodes(&zero, &zero);
+        index /= 2;
+    }
+    Ok(node)
+}
+
+/// Recomputes the root from the full list of leaves, e.g. replayed from events.
+pub fn root_from_leaves(leaves: &[[u8; 32]]) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = leaves.to_vec();
+    let mut zero = [0u8; 32];
+    for _ in 0..SESSION_TREE_DEPTH {
+        if level.is_empty() {
+            level.push(zero);
+        }
+        if level.len() % 2 == 1 {
+            level.push(zero);
+        }
+        level = level.chunks(2).map(|pair| hash_nodes(&pair[0], &pair[1])).collect();
+        zero = hash_nodes(&zero, &zero);
+    }
+    level[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u32) -> Vec<[u8; 32]> {
+        (0..n).map(|i| hash_leaf(&i.to_le_bytes())).collect()
+    }
+
+    #[test]
+    fn empty_tree_matches_rebuild() {
+        assert_eq!(empty_root(), root_from_leaves(&[]));
+    }
+
+    #[test]
+    fn incremental_root_matches_rebuild() {
+        let all = leaves(40);
+        let mut frontier = [[0u8; 32]; SESSION_TREE_DEPTH];
+        for (i, leaf) in all.iter().enumerate() {
+            let root = append(&mut frontier, i as u32, *leaf).unwrap();
+            assert_eq!(root, root_from_leaves(&all[..=i]), "after {} leaves", i + 1);
+        }
+    }
+
+    #[test]
+    fn fills_to_capacity_and_then_rejects() {
+        let all = leaves(SESSION_TREE_CAPACITY);
+        let mut frontier = [[0u8; 32]; SESSION_TREE_DEPTH];
+        let mut root = empty_root();
+        for (i, leaf) in all.iter().enumerate() {
+            root = append(&mut frontier, i as u32, *leaf).unwrap();
+        }
+        assert_eq!(root, root_from_leaves(&all));
+        assert!(append(&mut frontier, SESSION_TREE_CAPACITY, [1u8; 32]).is_err());
+    }
+
+    #[test]
+    fn leaves_and_nodes_are_domain_separated() {
+        let (left, right) = ([1u8; 32], [2u8; 32]);
+        let mut concatenated = left.to_vec();
+        concatenated.extend_from_slice(&right);
+        assert_ne!(hash_leaf(&concatenated), hash_nodes(&left, &right));
+    }
+}
@@ -0,0 +1,151 @@
+//! Session metadata schemas and the per-challenge goals they are checked against.
+
+use anchor_lang::prelude::*;
+
+use crate::ErrorCode;
+
+pub const MAX_LOCATION_LEN: usize = 64;
+pub const MAX_NOTES_LEN: usize = 160;
+pub const MAX_COURSE_ID_LEN: usize = 32;
+
+/// Which `SessionDetails` variant a challenge type expects.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, InitSpace)]
+pub enum MetadataSchema {
+    Fitness,
+    Education,
+    Meditation,
+    Custom,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct SessionMetadata {
+    pub duration_minutes: Option<u16>,
+    #[max_len(MAX_LOCATION_LEN)]
+    pub location: Option<String>,
+    #[max_len(MAX_NOTES_LEN)]
+    pub notes: Option<String>,
+    pub details: SessionDetails,
+}
+
+/// Type-specific measurements; the variant must match the challenge type's schema.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub enum SessionDetails {
+    None,
+    Fitness {
+        steps: u32,
+        distance_m: u32,
+        avg_heart_rate: Option<u8>,
+        activity: FitnessActivity,
+    },
+    Education {
+        pages: u16,
+        #[max_len(MAX_COURSE_ID_LEN)]
+        course_id: Option<String>,
+        quiz_score: Option<u8>, // 0-100
+    },
+    Meditation {
+        hrv_ms: Option<u16>, // Minutes are taken from duration_minutes
+    },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, InitSpace)]
+pub enum FitnessActivity {
+    Run,
+    Walk,
+    Cycle,
+    Swim,
+    Strength,
+    Other,
+}
+
+/// Targets a participant commits to at creation; every session must meet them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, InitSpace)]
+pub enum SessionGoal {
+    None,
+    Fitness {
+        min_steps: Option<u32>,
+        min_distance_m: Option<u32>,
+        activity: Option<FitnessActivity>,
+    },
+    Education {
+        min_pages: Option<u16>,
+        min_quiz_score: Option<u8>,
+    },
+    Meditation {
+        min_minutes: Option<u16>,
+    },
+}
+
+pub fn validate_session_goal(schema: &MetadataSchema, goal: &SessionGoal) -> Result<()> {
+    match (schema, goal) {
+        (_, SessionGoal::None) => {}
+        (MetadataSchema::Fitness, SessionGoal::Fitness { .. }) => {}
+        (MetadataSchema::Education, SessionGoal::Education { min_quiz_score, .. }) => {
+            require!(min_quiz_score.unwrap_or(0) <= 100, ErrorCode::InvalidSessionGoal);
+        }
+        (MetadataSchema::Meditation, SessionGoal::Meditation { .. }) => {}
+        _ => return err!(ErrorCode::InvalidSessionGoal),
+    }
+    Ok(())
+}
+
+pub fn validate_session_metadata(
+    schema: &MetadataSchema,
+    min_session_minutes: u16,
+    goal: &SessionGoal,
+    metadata: &SessionMetadata,
+) -> Result<()> {
+    // Free-form fields are bounded so sessions fit their fixed account size
+    if let Some(location) = &metadata.location {
+        require!(location.len() <= MAX_LOCATION_LEN, ErrorCode::LocationTooLong);
+    }
+    if let Some(notes) = &metadata.notes {
+        require!(notes.len() <= MAX_NOTES_LEN, ErrorCode::NotesTooLong);
+    }
+
+    let minutes = metadata.duration_minutes.unwrap_or(0);
+    require!(minutes >= min_session_minutes, ErrorCode::InvalidSessionDuration);
+
+    match (schema, &metadata.details) {
+        (
+            MetadataSchema::Fitness,
+            SessionDetails::Fitness { steps, distance_m, avg_heart_rate, activity },
+        ) => {
+            if let Some(bpm) = avg_heart_rate {
+                require!((30..=230).contains(bpm), ErrorCode::InvalidSessionDetails);
+            }
+            if let SessionGoal::Fitness { min_steps, min_distance_m, activity: goal_activity } = goal {
+                require!(*steps >= min_steps.unwrap_or(0), ErrorCode::SessionGoalNotMet);
+                require!(*distance_m >= min_distance_m.unwrap_or(0), ErrorCode::SessionGoalNotMet);
+                if let Some(wanted) = goal_activity {
+                    require!(wanted == activity, ErrorCode::SessionGoalNotMet);
+                }
+            }
+        }
+        (MetadataSchema::Education, SessionDetails::Education { pages, course_id, quiz_score }) => {
+            if let Some(id) = course_id {
+                require!(id.len() <= MAX_COURSE_ID_LEN, ErrorCode::InvalidSessionDetails);
+            }
+            require!(quiz_score.unwrap_or(0) <= 100, ErrorCode::InvalidSessionDetails);
+            if let SessionGoal::Education { min_pages, min_quiz_score } = goal {
+                require!(*pages >= min_pages.unwrap_or(0), ErrorCode::SessionGoalNotMet);
+                if let Some(min_score) = min_quiz_score {
+                    require!(
+                        quiz_score.is_some_and(|score| score >= *min_score),
+                        ErrorCode::SessionGoalNotMet
+                    );
+                }
+            }
+        }
+        (MetadataSchema::Meditation, SessionDetails::Meditation { .. }) => {
+            if let SessionGoal::Meditation { min_minutes } = goal {
+                require!(minutes >= min_minutes.unwrap_or(0), ErrorCode::SessionGoalNotMet);
+            }
+        }
+        (MetadataSchema::Custom, _) => {
+            // Custom challenges have flexible requirements
+        }
+        _ => return err!(ErrorCode::SessionDetailsMismatch),
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Content-addressed proof references stored on sessions.
+//!
+//! Clients submit a [`ProofInput`] in the textual form their storage uses;
+//! it is validated and reduced to the compact [`ProofRef`] kept on-chain.
+
+use anchor_lang::prelude::*;
+
+use crate::cid::{self, MAX_BINARY_LEN};
+use crate::ErrorCode;
+
+pub const MAX_PROOF_URL_LEN: usize = 200;
+
+/// Arweave transaction ids are 32 bytes rendered as unpadded base64url.
+const ARWEAVE_TX_ID_LEN: usize = 43;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Debug)]
+pub enum ProofInput {
+    IpfsCid { cid: String },
+    ArweaveTx { tx_id: String },
+    Sha256Digest { digest: [u8; 32] },
+    UrlWithDigest { url: String, sha256: [u8; 32] },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Debug, InitSpace)]
+pub enum ProofRef {
+    IpfsCid {
+        #[max_len(MAX_BINARY_LEN)]
+        cid: Vec<u8>, // Binary CID, see cid::parse
+    },
+    ArweaveTx { tx_id: [u8; 32] },
+    Sha256Digest { digest: [u8; 32] },
+    UrlWithDigest {
+        #[max_len(MAX_PROOF_URL_LEN)]
+        url: String,
+        sha256: [u8; 32],
+    },
+}
+
+impl ProofInput {
+    pub fn into_proof_ref(self) -> Result<ProofRef> {
+        match self {
+            ProofInput::IpfsCid { cid } => {
+                let cid = cid::parse(&cid).map_err(|err| {
+                    msg!("Rejected proof CID: {:?}", err);
+                    error!(ErrorCode::InvalidIPFSHash)
+                })?;
+                Ok(ProofRef::IpfsCid { cid })
+            }
+            ProofInput::ArweaveTx { tx_id } => {
+                let tx_id = decode_arweave_tx_id(&tx_id).ok_or(ErrorCode::InvalidArweaveTx)?;
+                Ok(ProofRef::ArweaveTx { tx_id })
+            }
+            ProofInput::Sha256Digest { digest } => {
+                require!(digest != [0u8; 32], ErrorCode::InvalidProofDigest);
+                Ok(ProofRef::Sha256Digest { digest })
+            }
+            ProofInput::UrlWithDigest { url, sha256 } => {
+                require!(is_valid_proof_url(&url), ErrorCode::InvalidProofUrl);
+                require!(sha256 != [0u8; 32], ErrorCode::InvalidProofDigest);
+                Ok(ProofRef::UrlWithDigest { url, sha256 })
+            }
+        }
+    }
+}
+
+fn decode_arweave_tx_id(text: &str) -> Option<[u8; 32]> {
+    if text.len() != ARWEAVE_TX_ID_LEN {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    let mut written = 0;
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        } as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out[written] = (buffer >> bits) as u8;
+            written += 1;
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // 43 chars carry 258 bits; the 2 spare bits must be zero for a canonical id
+    (buffer == 0).then_some(out)
+}
+
+fn is_valid_proof_url(url: &str) -> bool {
+    url.len() <= MAX_PROOF_URL_LEN
+        && url.len() > "https://".len()
+        && url.starts_with("https://")
+        && url.bytes().all(|c| c.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_arweave_tx_id() {
+        let id = "bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U";
+        let bytes = decode_arweave_tx_id(id).unwrap();
+        assert_eq!(bytes[0], 0x6c);
+        assert_eq!(bytes[31], 0xf5);
+
+        assert!(decode_arweave_tx_id(&id[..42]).is_none());
+        assert!(decode_arweave_tx_id("bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt+U").is_none());
+        // non-zero trailing bits
+        assert!(decode_arweave_tx_id("bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_V").is_none());
+    }
+
+    #[test]
+    fn validates_proof_urls() {
+        assert!(is_valid_proof_url("https://evidence.example.com/sessions/42.json"));
+        assert!(!is_valid_proof_url("http://evidence.example.com/42.json"));
+        assert!(!is_valid_proof_url("https://"));
+        assert!(!is_valid_proof_url("https://example.com/a b"));
+        assert!(!is_valid_proof_url(&format!("https://{}", "a".repeat(MAX_PROOF_URL_LEN))));
+    }
+
+    #[test]
+    fn rejects_zero_digests() {
+        let input = ProofInput::Sha256Digest { digest: [0u8; 32] };
+        assert!(input.into_proof_ref().is_err());
+
+        let input = ProofInput::UrlWithDigest {
+            url: "https://example.com/proof".to_string(),
+            sha256: [7u8; 32],
+        };
+        assert!(input.into_proof_ref().is_ok());
+    }
+
+    #[test]
+    fn init_space_covers_every_variant() {
+        let largest = ProofRef::UrlWithDigest {
+            url: "u".repeat(MAX_PROOF_URL_LEN),
+            sha256: [0u8; 32],
+        };
+        assert_eq!(largest.try_to_vec().unwrap().len(), ProofRef::INIT_SPACE);
+
+        let cid = ProofRef::IpfsCid { cid: vec![0u8; MAX_BINARY_LEN] };
+        assert!(cid.try_to_vec().unwrap().len() <= ProofRef::INIT_SPACE);
+    }
+}
@@ -0,0 +1,257 @@
+//! When a challenge's sessions may be recorded.
+//!
+//! Calendar modes work on local days: `utc_offset_minutes` shifts the UTC
+//! timestamp before it is split into days, so "daily" means the participant's
+//! midnight rather than UTC midnight.
+
+use anchor_lang::prelude::*;
+
+use crate::math::{self, SECONDS_PER_DAY};
+use crate::ErrorCode;
+
+const SECONDS_PER_WEEK: i64 = 7 * SECONDS_PER_DAY;
+const MAX_UTC_OFFSET_MINUTES: u16 = 14 * 60;
+
+/// Session timestamps kept on the challenge for the rolling weekly window.
+pub const RECENT_SESSIONS: usize = 7;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug, InitSpace)]
+pub enum Schedule {
+    /// At least `hours` between consecutive sessions.
+    MinInterval { hours: u16 },
+    /// At most `sessions` within any rolling 7-day window.
+    PerWeek { sessions: u8 },
+    /// One session per local day, only on the weekdays in `mask` (bit 0 = Monday).
+    Weekdays { mask: u8, utc_offset_minutes: i16 },
+    /// One session per local day.
+    Daily { utc_offset_minutes: i16 },
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<()> {
+        let valid = match *self {
+            Schedule::MinInterval { hours } => (1..=7 * 24).contains(&hours),
+            Schedule::PerWeek { sessions } => (1..=RECENT_SESSIONS as u8).contains(&sessions),
+            Schedule::Weekdays { mask, utc_offset_minutes } => {
+                mask != 0 && mask < 1 << 7 && utc_offset_minutes.unsigned_abs() <= MAX_UTC_OFFSET_MINUTES
+            }
+            Schedule::Daily { utc_offset_minutes } => utc_offset_minutes.unsigned_abs() <= MAX_UTC_OFFSET_MINUTES,
+        };
+        require!(valid, ErrorCode::InvalidSchedule);
+        Ok(())
+    }
+
+    /// Checks a session at `now` against the previous ones, newest first in
+    /// `recent` (zero = unused slot).
+    pub fn check_session(&self, recent: &[i64; RECENT_SESSIONS], now: i64) -> Result<()> {
+        let last = recent[0];
+        match *self {
+            Schedule::MinInterval { hours } => {
+                if last > 0 {
+                    let earliest = math::add_seconds(last, math::hours_to_seconds(hours))?;
+                    require!(now >= earliest, ErrorCode::SessionTooSoon);
+                }
+            }
+            Schedule::PerWeek { sessions } => {
+                let in_window = recent
+                    .iter()
+                    .filter(|&&at| at > 0 && now - at < SECONDS_PER_WEEK)
+                    .count();
+                require!(in_window < sessions as usize, ErrorCode::WeeklyLimitReached);
+            }
+            Schedule::Weekdays { mask, utc_offset_minutes } => {
+                let today = local_day(now, utc_offset_minutes)?;
+                require!(mask & (1 << weekday(today)) != 0, ErrorCode::NotAScheduledDay);
+                if last > 0 {
+                    require!(local_day(last, utc_offset_minutes)? < today, ErrorCode::AlreadyCheckedInToday);
+                }
+            }
+            Schedule::Daily { utc_offset_minutes } => {
+                if last > 0 {
+                    require!(
+                        local_day(last, utc_offset_minutes)? < local_day(now, utc_offset_minutes)?,
+                        ErrorCode::AlreadyCheckedInToday
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// How many sessions the schedule admits between `start` and `end`.
+    pub fn capacity(&self, start: i64, end: i64) -> Result<u32> {
+        if end <= start {
+            return Ok(0);
+        }
+        let elapsed = math::sub_seconds(end, start)?;
+        let last_second = math::sub_seconds(end, 1)?;
+        let count = match *self {
+            Schedule::MinInterval { hours } => elapsed / math::hours_to_seconds(hours) + 1,
+            Schedule::PerWeek { sessions } => {
+                let weeks = math::add_seconds(elapsed, SECONDS_PER_WEEK - 1)? / SECONDS_PER_WEEK;
+                weeks.checked_mul(sessions as i64).ok_or(ErrorCode::ArithmeticOverflow)?
+            }
+            Schedule::Weekdays { mask, utc_offset_minutes } => {
+                let (first, last) = (local_day(start, utc_offset_minutes)?, local_day(last_second, utc_offset_minutes)?);
+                (first..=last).filter(|&day| mask & (1 << weekday(day)) != 0).count() as i64
+            }
+            Schedule::Daily { utc_offset_minutes } => {
+                let days = local_day(last_second, utc_offset_minutes)? - local_day(start, utc_offset_minutes)?;
+                days + 1 // both are day numbers of in-range timestamps, far from i64::MAX
+            }
+        };
+        u32::try_from(count).map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+    }
+
+    /// Windows between `start` and `end` that passed without a session.
+    /// Interval schedules have no windows to miss.
+    pub fn missed_windows(&self, start: i64, end: i64, completed: u32) -> Result<u32> {
+        match self {
+            Schedule::MinInterval { .. } => Ok(0),
+            _ => Ok(self.capacity(start, end)?.saturating_sub(completed)),
+        }
+    }
+}
+
+/// Records `now` as the newest session time.
+pub fn push_recent(recent: &mut [i64; RECENT_SESSIONS], now: i64) {
+    recent.copy_within(..RECENT_SESSIONS - 1, 1);
+    recent[0] = now;
+}
+
+/// Forgets a revoked session so it no longer counts against the schedule.
+/// Sessions already pushed out of the history are left alone.
+pub fn remove_recent(recent: &mut [i64; RECENT_SESSIONS], at: i64) {
+    if let Some(index) = recent.iter().position(|&time| time == at) {
+        recent.copy_within(index + 1.., index);
+        recent[RECENT_SESSIONS - 1] = 0;
+    }
+}
+
+fn local_day(timestamp: i64, utc_offset_minutes: i16) -> Result<i64> {
+    let local = math::add_seconds(timestamp, utc_offset_minutes as i64 * 60)?;
+    Ok(local.div_euclid(SECONDS_PER_DAY))
+}
+
+/// 0 = Monday; day 0 of the Unix epoch was a Thursday.
+fn weekday(day: i64) -> u32 {
+    (day + 3).rem_euclid(7) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01 00:00:00 UTC, a Monday
+    const MONDAY: i64 = 1_704_067_200;
+    const HOUR: i64 = 3600;
+
+    fn history(times: &[i64]) -> [i64; RECENT_SESSIONS] {
+        let mut recent = [0; RECENT_SESSIONS];
+        for &at in times {
+            push_recent(&mut recent, at);
+        }
+        recent
+    }
+
+    #[test]
+    fn weekday_of_known_dates() {
+        assert_eq!(weekday(local_day(MONDAY, 0).unwrap()), 0);
+        assert_eq!(weekday(local_day(MONDAY + 5 * SECONDS_PER_DAY, 0).unwrap()), 5);
+        assert_eq!(weekday(local_day(0, 0).unwrap()), 3);
+        // 23:00 UTC Sunday is already Monday in UTC+2
+        assert_eq!(weekday(local_day(MONDAY - HOUR, 120).unwrap()), 0);
+        assert_eq!(weekday(local_day(MONDAY - HOUR, 0).unwrap()), 6);
+    }
+
+    #[test]
+    fn min_interval() {
+        let schedule = Schedule::MinInterval { hours: 24 };
+        assert!(schedule.check_session(&history(&[]), MONDAY).is_ok());
+        assert!(schedule.check_session(&history(&[MONDAY]), MONDAY + 23 * HOUR).is_err());
+        assert!(schedule.check_session(&history(&[MONDAY]), MONDAY + 24 * HOUR).is_ok());
+    }
+
+    #[test]
+    fn per_week_uses_a_rolling_window() {
+        let schedule = Schedule::PerWeek { sessions: 3 };
+        let week = [MONDAY, MONDAY + HOUR, MONDAY + 2 * HOUR];
+        assert!(schedule.check_session(&history(&week[..2]), MONDAY + 3 * HOUR).is_ok());
+        assert!(schedule.check_session(&history(&week), MONDAY + 3 * HOUR).is_err());
+        // The first session leaves the window exactly 7 days later
+        assert!(schedule.check_session(&history(&week), MONDAY + SECONDS_PER_WEEK - 1).is_err());
+        assert!(schedule.check_session(&history(&week), MONDAY + SECONDS_PER_WEEK).is_ok());
+    }
+
+    #[test]
+    fn revoked_sessions_leave_the_history() {
+        let mut recent = history(&[MONDAY, MONDAY + HOUR, MONDAY + 2 * HOUR]);
+        remove_recent(&mut recent, MONDAY + 2 * HOUR);
+        assert_eq!(recent, history(&[MONDAY, MONDAY + HOUR]));
+        remove_recent(&mut recent, MONDAY);
+        assert_eq!(recent, history(&[MONDAY + HOUR]));
+        // Unknown times are ignored
+        remove_recent(&mut recent, MONDAY + 5 * HOUR);
+        assert_eq!(recent, history(&[MONDAY + HOUR]));
+
+        // A revoked daily check-in frees the day again
+        let schedule = Schedule::Daily { utc_offset_minutes: 0 };
+        let mut recent = history(&[MONDAY + 4 * HOUR]);
+        assert!(schedule.check_session(&recent, MONDAY + 6 * HOUR).is_err());
+        remove_recent(&mut recent, MONDAY + 4 * HOUR);
+        assert!(schedule.check_session(&recent, MONDAY + 6 * HOUR).is_ok());
+    }
+
+    #[test]
+    fn weekdays_only_on_listed_days_once_per_day() {
+        let schedule = Schedule::Weekdays { mask: 0b0010101, utc_offset_minutes: 0 }; // Mon, Wed, Fri
+        assert!(schedule.check_session(&history(&[]), MONDAY + 8 * HOUR).is_ok());
+        assert!(schedule.check_session(&history(&[]), MONDAY + SECONDS_PER_DAY).is_err());
+        assert!(schedule.check_session(&history(&[MONDAY + 8 * HOUR]), MONDAY + 20 * HOUR).is_err());
+        assert!(schedule
+            .check_session(&history(&[MONDAY + 8 * HOUR]), MONDAY + 2 * SECONDS_PER_DAY)
+            .is_ok());
+    }
+
+    #[test]
+    fn daily_respects_the_utc_offset() {
+        let schedule = Schedule::Daily { utc_offset_minutes: -300 }; // UTC-5
+        // 04:00 and 06:00 UTC fall on different local days in UTC-5
+        assert!(schedule.check_session(&history(&[MONDAY + 4 * HOUR]), MONDAY + 6 * HOUR).is_ok());
+        assert!(Schedule::Daily { utc_offset_minutes: 0 }
+            .check_session(&history(&[MONDAY + 4 * HOUR]), MONDAY + 6 * HOUR)
+            .is_err());
+    }
+
+    #[test]
+    fn capacity_and_missed_windows() {
+        let four_weeks = MONDAY + 4 * SECONDS_PER_WEEK;
+        assert_eq!(Schedule::Daily { utc_offset_minutes: 0 }.capacity(MONDAY, four_weeks).unwrap(), 28);
+        assert_eq!(Schedule::PerWeek { sessions: 3 }.capacity(MONDAY, four_weeks).unwrap(), 12);
+        let weekdays = Schedule::Weekdays { mask: 0b0011111, utc_offset_minutes: 0 };
+        assert_eq!(weekdays.capacity(MONDAY, four_weeks).unwrap(), 20);
+        assert_eq!(Schedule::MinInterval { hours: 24 }.capacity(MONDAY, MONDAY + 7 * SECONDS_PER_DAY).unwrap(), 8);
+
+        assert_eq!(weekdays.missed_windows(MONDAY, four_weeks, 17).unwrap(), 3);
+        assert_eq!(weekdays.missed_windows(MONDAY, four_weeks, 25).unwrap(), 0);
+        assert_eq!(Schedule::MinInterval { hours: 24 }.missed_windows(MONDAY, four_weeks, 0).unwrap(), 0);
+        assert_eq!(Schedule::Daily { utc_offset_minutes: 0 }.capacity(MONDAY, MONDAY).unwrap(), 0);
+        // Ranges that cannot be counted fail instead of wrapping
+        assert!(Schedule::Daily { utc_offset_minutes: 0 }.capacity(i64::MIN, i64::MAX).is_err());
+        assert!(Schedule::MinInterval { hours: 1 }.capacity(0, i64::MAX).is_err());
+        assert!(local_day(i64::MAX, 60).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        assert!(Schedule::MinInterval { hours: 0 }.validate().is_err());
+        assert!(Schedule::PerWeek { sessions: 8 }.validate().is_err());
+        assert!(Schedule::Weekdays { mask: 0, utc_offset_minutes: 0 }.validate().is_err());
+        assert!(Schedule::Weekdays { mask: 0x80, utc_offset_minutes: 0 }.validate().is_err());
+        assert!(Schedule::Daily { utc_offset_minutes: 15 * 60 }.validate().is_err());
+        assert!(Schedule::Daily { utc_offset_minutes: -14 * 60 }.validate().is_ok());
+        // i16::MIN has no positive counterpart
+        assert!(Schedule::Daily { utc_offset_minutes: i16::MIN }.validate().is_err());
+        assert!(Schedule::Weekdays { mask: 1, utc_offset_minutes: i16::MIN }.validate().is_err());
+    }
+}
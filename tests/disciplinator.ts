@@ -6,6 +6,8 @@ import {
   Keypair, 
   SystemProgram, 
   SYSVAR_RENT_PUBKEY,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  Ed25519Program,
   LAMPORTS_PER_SOL
 } from "@solana/web3.js";
 import { 
@@ -16,6 +18,28 @@ import {
   getAccount
 } from "@solana/spl-token";
 import { assert } from "chai";
+import { createHash } from "crypto";
+
+const SESSION_TREE_DEPTH = 9;
+
+const sha256 = (...parts: Buffer[]) => createHash("sha256").update(Buffer.concat(parts)).digest();
+
+// Mirrors merkle::root_from_leaves so trees can be rebuilt from SessionAppended events
+function rootFromLeaves(leaves: Buffer[]): Buffer {
+  let level = [...leaves];
+  let zero = Buffer.alloc(32);
+  for (let depth = 0; depth < SESSION_TREE_DEPTH; depth++) {
+    if (level.length === 0) level.push(zero);
+    if (level.length % 2 === 1) level.push(zero);
+    const next: Buffer[] = [];
+    for (let i = 0; i < level.length; i += 2) {
+      next.push(sha256(Buffer.from([1]), level[i], level[i + 1]));
+    }
+    level = next;
+    zero = sha256(Buffer.from([1]), zero, zero);
+  }
+  return level[0];
+}
 
 describe("disciplinator", () => {
   // Configure the client to use the local cluster.
@@ -44,10 +68,71 @@ describe("disciplinator", () => {
   let rewardStatePda: PublicKey;
   let userStatsPda: PublicKey;
   let challengePda: PublicKey;
+  let fitnessTypePda: PublicKey;
+  let meditationTypePda: PublicKey;
+
+  // Challenge PDAs are seeded with the participant's own creation counter
+  const nextChallengeIndex = async (user: PublicKey): Promise<anchor.BN> => {
+    const [statsPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user_stats"), user.toBuffer()],
+      program.programId
+    );
+    const stats = await program.account.userStats.fetchNullable(statsPda);
+    return stats ? stats.challengesCreated : new anchor.BN(0);
+  };
+
+  // Mirrors stats_shard_index: participants are spread over STATS_SHARD_COUNT shards
+  const STATS_SHARD_COUNT = 8;
+  const statsShardFor = (user: PublicKey): PublicKey =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("stats_shard"), Buffer.from([user.toBuffer()[0] % STATS_SHARD_COUNT])],
+      program.programId
+    )[0];
+
+  // UserStats only exist once a user has opened a challenge of their own
+  const openOwnChallenge = async (user: Keypair): Promise<PublicKey> => {
+    const tokenAccount = await createAccount(provider.connection, user, mint, user.publicKey);
+    await mintTo(provider.connection, authority, mint, tokenAccount, authority, TEST_DEPOSIT);
+
+    const challengeIndex = await nextChallengeIndex(user.publicKey);
+    const [ownChallengePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("challenge"), user.publicKey.toBuffer(), challengeIndex.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [ownStatsPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user_stats"), user.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .createChallenge(new anchor.BN(TEST_DEPOSIT), 10, 30, verifier.publicKey, FITNESS_TYPE_ID, { none: {} }, null, null, null, null, null)
+      .accounts({
+        challenge: ownChallengePda,
+        participant: user.publicKey,
+        participantTokenAccount: tokenAccount,
+        config: configPda,
+        challengeType: fitnessTypePda,
+        acceptedMint: mint,
+        vault: vaultPda,
+        userStats: ownStatsPda,
+        statsShard: statsShardFor(user.publicKey),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    return ownStatsPda;
+  };
   
   const USDT_DECIMALS = 6;
   const MIN_DEPOSIT = 5_000_000; // 5 USDT
   const TEST_DEPOSIT = 10_000_000; // 10 USDT
+  const FITNESS_TYPE_ID = 0;
+  const MEDITATION_TYPE_ID = 2;
+  const FITNESS_DETAILS = {
+    fitness: { steps: 6000, distanceM: 5000, avgHeartRate: 140, activity: { run: {} } }
+  };
 
   before(async () => {
     // Airdrop SOL to test accounts
@@ -179,6 +264,69 @@ describe("disciplinator", () => {
       .rpc();
       
     console.log("Initialize transaction signature:", tx);
+
+    // Register the challenge types used by the tests
+    const challengeTypes = [
+      { id: FITNESS_TYPE_ID, name: "Fitness", schema: { fitness: {} }, minSessionMinutes: 20 },
+      { id: MEDITATION_TYPE_ID, name: "Meditation", schema: { meditation: {} }, minSessionMinutes: 10 },
+    ];
+    for (const challengeType of challengeTypes) {
+      const [challengeTypePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("challenge_type"), new anchor.BN(challengeType.id).toArrayLike(Buffer, "le", 2)],
+        program.programId
+      );
+
+      await program.methods
+        .createChallengeType(challengeType.id, {
+          name: challengeType.name,
+          schema: challengeType.schema,
+          minSessionMinutes: challengeType.minSessionMinutes,
+          minDeposit: new anchor.BN(MIN_DEPOSIT),
+          maxDeposit: new anchor.BN(10_000_000_000),
+          defaultGracePeriods: 3,
+          maxGracePeriods: 5,
+          defaultGraceDays: 3,
+          maxGraceDays: 7,
+          graceRefundPenaltyBps: 500,
+          minSessions: 1,
+          maxSessions: 365,
+          minDurationDays: 7,
+          maxDurationDays: 365,
+          active: true,
+        })
+        .accounts({
+          challengeType: challengeTypePda,
+          config: configPda,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      if (challengeType.id === FITNESS_TYPE_ID) {
+        fitnessTypePda = challengeTypePda;
+      } else {
+        meditationTypePda = challengeTypePda;
+      }
+    }
+
+    // Global statistics are spread over shards so creations don't contend on Config
+    for (let index = 0; index < STATS_SHARD_COUNT; index++) {
+      const [statsShardPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("stats_shard"), Buffer.from([index])],
+        program.programId
+      );
+      await program.methods
+        .initStatsShard(index)
+        .accounts({
+          statsShard: statsShardPda,
+          config: configPda,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+    }
   });
 
   describe("Initialization", () => {
@@ -199,12 +347,12 @@ describe("disciplinator", () => {
   describe("Challenge Management", () => {
     before(async () => {
       // Calculate challenge PDA for the first challenge (ID 0)
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
       [challengePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           participant.publicKey.toBuffer(),
-          configAccount.totalChallenges.toArrayLike(Buffer, "le", 8)
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       );
@@ -217,16 +365,24 @@ describe("disciplinator", () => {
           30, // 30 sessions
           30, // 30 days
           null, // no verifier
-          { fitness: {} } // fitness challenge
+          FITNESS_TYPE_ID, // fitness challenge type
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
         )
         .accounts({
           challenge: challengePda,
           participant: participant.publicKey,
           participantTokenAccount: participantTokenAccount,
           config: configPda,
+          challengeType: fitnessTypePda,
           acceptedMint: mint,
           vault: vaultPda,
           userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
@@ -251,18 +407,107 @@ describe("disciplinator", () => {
         TOKEN_PROGRAM_ID
       );
       assert.equal(vaultAccount.amount.toString(), TEST_DEPOSIT.toString());
+
+      // Global statistics land in the participant's shard
+      const shard = await program.account.statsShard.fetch(statsShardFor(participant.publicKey));
+      assert.equal(shard.challengesCreated.toNumber(), 1);
+      assert.equal(shard.volume.toNumber(), TEST_DEPOSIT);
+    });
+
+    it("Should let the participant top up the deposit", async () => {
+      const topUp = 5_000_000; // 5 USDT
+      const increaseDeposit = (amount: anchor.BN) =>
+        program.methods
+          .increaseDeposit(amount)
+          .accounts({
+            challenge: challengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: fitnessTypePda,
+            template: null,
+            acceptedMint: mint,
+            vault: vaultPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([participant])
+          .rpc();
+
+      await increaseDeposit(new anchor.BN(topUp));
+
+      const challenge = await program.account.challenge.fetch(challengePda);
+      assert.equal(challenge.depositAmount.toNumber(), TEST_DEPOSIT + topUp);
+      const vaultAccount = await getAccount(provider.connection, vaultPda, undefined, TOKEN_PROGRAM_ID);
+      assert.equal(vaultAccount.amount.toString(), (TEST_DEPOSIT + topUp).toString());
+      const shard = await program.account.statsShard.fetch(statsShardFor(participant.publicKey));
+      assert.equal(shard.volume.toNumber(), TEST_DEPOSIT + topUp);
+
+      // The total stake is still capped by the protocol maximum
+      try {
+        await increaseDeposit(new anchor.BN(10_000_000_000));
+        assert.fail("Should have failed above the maximum deposit");
+      } catch (error) {
+        assert.include(error.message, "DepositTooLarge");
+      }
+
+      // ...and by the challenge type's current maximum, which may be lower
+      const fitnessType = await program.account.challengeTypeConfig.fetch(fitnessTypePda);
+      const updateFitnessMax = (maxDeposit: anchor.BN) =>
+        program.methods
+          .updateChallengeType({
+            name: fitnessType.name,
+            schema: fitnessType.schema,
+            minSessionMinutes: fitnessType.minSessionMinutes,
+            minDeposit: fitnessType.minDeposit,
+            maxDeposit,
+            defaultGracePeriods: fitnessType.defaultGracePeriods,
+            maxGracePeriods: fitnessType.maxGracePeriods,
+            defaultGraceDays: fitnessType.defaultGraceDays,
+            maxGraceDays: fitnessType.maxGraceDays,
+            graceRefundPenaltyBps: fitnessType.graceRefundPenaltyBps,
+            minSessions: fitnessType.minSessions,
+            maxSessions: fitnessType.maxSessions,
+            minDurationDays: fitnessType.minDurationDays,
+            maxDurationDays: fitnessType.maxDurationDays,
+            active: fitnessType.active,
+          })
+          .accounts({
+            challengeType: fitnessTypePda,
+            config: configPda,
+            authority: authority.publicKey,
+          })
+          .signers([authority])
+          .rpc();
+
+      await updateFitnessMax(new anchor.BN(TEST_DEPOSIT + topUp));
+      try {
+        await increaseDeposit(new anchor.BN(1));
+        assert.fail("Should have failed above the type's maximum deposit");
+      } catch (error) {
+        assert.include(error.message, "DepositTooLarge");
+      } finally {
+        await updateFitnessMax(fitnessType.maxDeposit);
+      }
+
+      try {
+        await increaseDeposit(new anchor.BN(0));
+        assert.fail("Should have failed for a zero top-up");
+      } catch (error) {
+        assert.include(error.message, "InvalidAmount");
+      }
     });
 
     it("Should fail to create challenge with insufficient deposit", async () => {
       const smallDeposit = 1_000_000; // 1 USDT - below minimum
       
       // Create a new challenge PDA for a different challenge ID
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
       const [newChallengePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           participant.publicKey.toBuffer(),
-          new anchor.BN(1).toArrayLike(Buffer, "le", 8) // Use challenge ID 1
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       );
@@ -274,16 +519,24 @@ describe("disciplinator", () => {
             10,
             7,
             null,
-            { fitness: {} }
+            FITNESS_TYPE_ID,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
           )
           .accounts({
             challenge: newChallengePda,
             participant: participant.publicKey,
             participantTokenAccount: participantTokenAccount,
             config: configPda,
+            challengeType: fitnessTypePda,
             acceptedMint: mint,
             vault: vaultPda,
             userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
           })
@@ -295,6 +548,187 @@ describe("disciplinator", () => {
         assert.include(error.message, "DepositTooSmall");
       }
     });
+
+    it("Should reject challenges for an inactive challenge type", async () => {
+      const sleepTypeId = 7;
+      const [sleepTypePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("challenge_type"), new anchor.BN(sleepTypeId).toArrayLike(Buffer, "le", 2)],
+        program.programId
+      );
+
+      await program.methods
+        .createChallengeType(sleepTypeId, {
+          name: "Sleep",
+          schema: { custom: {} },
+          minSessionMinutes: 0,
+          minDeposit: new anchor.BN(MIN_DEPOSIT),
+          maxDeposit: new anchor.BN(100_000_000),
+          defaultGracePeriods: 1,
+          maxGracePeriods: 1,
+          defaultGraceDays: 3,
+          maxGraceDays: 3,
+          graceRefundPenaltyBps: 0,
+          minSessions: 7,
+          maxSessions: 60,
+          minDurationDays: 7,
+          maxDurationDays: 60,
+          active: false, // Not yet launched
+        })
+        .accounts({
+          challengeType: sleepTypePda,
+          config: configPda,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const sleepType = await program.account.challengeTypeConfig.fetch(sleepTypePda);
+      assert.equal(sleepType.name, "Sleep");
+      assert.isFalse(sleepType.active);
+
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [sleepChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .createChallenge(
+            new anchor.BN(TEST_DEPOSIT),
+            14,
+            14,
+            null,
+            sleepTypeId,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
+          )
+          .accounts({
+            challenge: sleepChallengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: sleepTypePda,
+            acceptedMint: mint,
+            vault: vaultPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([participant])
+          .rpc();
+
+        assert.fail("Should have failed with inactive challenge type");
+      } catch (error) {
+        assert.include(error.message, "ChallengeTypeInactive");
+      }
+    });
+
+    it("Should reject a schedule that cannot fit every session", async () => {
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [scheduledChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .createChallenge(
+            new anchor.BN(TEST_DEPOSIT),
+            12, // 3x per week over 14 days only admits 6 sessions
+            14,
+            null,
+            FITNESS_TYPE_ID,
+            { none: {} },
+            { perWeek: { sessions: 3 } },
+            null,
+            null,
+            null,
+            null
+          )
+          .accounts({
+            challenge: scheduledChallengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: fitnessTypePda,
+            acceptedMint: mint,
+            vault: vaultPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([participant])
+          .rpc();
+
+        assert.fail("Should have failed with an unsatisfiable schedule");
+      } catch (error) {
+        assert.include(error.message, "InvalidSchedule");
+      }
+    });
+
+    it("Should reject grace terms beyond the challenge type's limits", async () => {
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [graceChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .createChallenge(
+            new anchor.BN(TEST_DEPOSIT),
+            30,
+            30,
+            null,
+            FITNESS_TYPE_ID,
+            { none: {} },
+            null,
+            { periods: 2, days: 10 }, // fitness allows at most 7-day grace periods
+            null,
+            null,
+            null
+          )
+          .accounts({
+            challenge: graceChallengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: fitnessTypePda,
+            acceptedMint: mint,
+            vault: vaultPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([participant])
+          .rpc();
+
+        assert.fail("Should have failed with oversized grace terms");
+      } catch (error) {
+        assert.include(error.message, "InvalidGraceTerms");
+      }
+    });
   });
 
   describe("Session Management", () => {
@@ -309,12 +743,12 @@ describe("disciplinator", () => {
       await new Promise(resolve => setTimeout(resolve, 500));
       
       // Create a new challenge with verifier for session tests
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
       [sessionChallengePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           participant.publicKey.toBuffer(),
-          configAccount.totalChallenges.toArrayLike(Buffer, "le", 8)
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       );
@@ -326,16 +760,24 @@ describe("disciplinator", () => {
           10, // 10 sessions  
           30, // 30 days
           verifier.publicKey, // With verifier
-          { fitness: {} }
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
         )
         .accounts({
           challenge: sessionChallengePda,
           participant: participant.publicKey,
           participantTokenAccount: participantTokenAccount,
           config: configPda,
+          challengeType: fitnessTypePda,
           acceptedMint: mint,
           vault: vaultPda,
           userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
@@ -356,11 +798,12 @@ describe("disciplinator", () => {
     it("Should mark a session as complete", async () => {
       const tx = await program.methods
         .markSessionComplete(
-          "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU",
+          { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
           {
             durationMinutes: 45,
             location: "Home Gym",
-            notes: "Great workout session"
+            notes: "Great workout session",
+            details: FITNESS_DETAILS
           }
         )
         .accounts({
@@ -369,6 +812,7 @@ describe("disciplinator", () => {
           signer: verifier.publicKey,
           session: sessionPda,
           userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
           systemProgram: SystemProgram.programId,
         })
         .signers([verifier])
@@ -380,7 +824,9 @@ describe("disciplinator", () => {
       const session = await program.account.session.fetch(sessionPda);
       assert.equal(session.challenge.toString(), sessionChallengePda.toString());
       assert.equal(session.sessionNumber, 1);
-      assert.equal(session.proofIpfsHash, "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU");
+      // CIDv0 is stored as its bare sha2-256 multihash
+      assert.equal(session.proof.ipfsCid.cid.length, 34);
+      assert.equal(session.proof.ipfsCid.cid[0], 0x12);
       assert.equal(session.verifiedBy.toString(), verifier.publicKey.toString());
       assert.isFalse(session.autoVerified);
       
@@ -402,11 +848,12 @@ describe("disciplinator", () => {
       try {
         await program.methods
           .markSessionComplete(
-            "QmTXKz6FwjhZGNKUjC2qv3XN7efdF8hSYrUwPxEGdBnNkS",
+            { ipfsCid: { cid: "QmTXKz6FwjhZGNKUjC2qv3XN7efdF8hSYrUwPxEGdBnNkS" } },
             {
               durationMinutes: 30,
               location: null,
-              notes: null
+              notes: null,
+              details: FITNESS_DETAILS
             }
           )
           .accounts({
@@ -415,6 +862,7 @@ describe("disciplinator", () => {
             signer: verifier.publicKey,
             session: session2Pda,
             userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
             systemProgram: SystemProgram.programId,
           })
           .signers([verifier])
@@ -425,48 +873,1733 @@ describe("disciplinator", () => {
         assert.include(error.message, "SessionTooSoon");
       }
     });
-  });
 
-  describe("Grace Period", () => {
-    it("Should allow using a grace period", async () => {
-      const [gracePda] = PublicKey.findProgramAddressSync(
+    it("Should enforce the participant's session goal", async () => {
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [goalChallengePda] = PublicKey.findProgramAddressSync(
         [
-          Buffer.from("grace"),
-          challengePda.toBuffer(),
-          Buffer.from([0]) // grace_periods_used as u8
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       );
 
-      const tx = await program.methods
-        .useGracePeriod("Unexpected work commitment")
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          10,
+          30,
+          verifier.publicKey,
+          FITNESS_TYPE_ID,
+          { fitness: { minSteps: 10_000, minDistanceM: null, activity: null } }, // 10k steps
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
+        )
         .accounts({
-          challenge: challengePda,
+          challenge: goalChallengePda,
           participant: participant.publicKey,
-          graceRecord: gracePda,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
         .signers([participant])
         .rpc();
 
-      console.log("Use grace period transaction signature:", tx);
-      
-      // Verify grace period was recorded
-      const graceRecord = await program.account.gracePeriodRecord.fetch(gracePda);
-      assert.equal(graceRecord.challenge.toString(), challengePda.toString());
-      assert.equal(graceRecord.reason, "Unexpected work commitment");
-      
-      // Verify challenge end time was extended
-      const challenge = await program.account.challenge.fetch(challengePda);
-      assert.equal(challenge.gracePeriodsUsed, 1);
+      const [goalSessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), goalChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .markSessionComplete(
+            { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+            {
+              durationMinutes: 45,
+              location: null,
+              notes: null,
+              details: FITNESS_DETAILS // 6000 steps
+            }
+          )
+          .accounts({
+            challenge: goalChallengePda,
+            participant: participant.publicKey,
+            signer: verifier.publicKey,
+            session: goalSessionPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([verifier])
+          .rpc();
+
+        assert.fail("Should have failed with session goal not met");
+      } catch (error) {
+        assert.include(error.message, "SessionGoalNotMet");
+      }
     });
   });
 
-  describe("Protocol Controls", () => {
-    it("Should pause the protocol", async () => {
-      const tx = await program.methods
-        .pauseProtocol()
-        .accounts({
+  describe("Session Attestations", () => {
+    const attester = Keypair.generate();
+    const relayer = Keypair.generate();
+    let attestedChallengePda: PublicKey;
+    let attesterRecordPda: PublicKey;
+
+    // Mirrors the Borsh layout of AttestationMessage, prefixed with its domain
+    const encodeAttestation = (
+      challenge: PublicKey,
+      sessionNumber: number,
+      proofHash: string,
+      durationMinutes: number
+    ): Buffer => {
+      const u32 = (n: number) => {
+        const b = Buffer.alloc(4);
+        b.writeUInt32LE(n);
+        return b;
+      };
+      // SessionDetails::Fitness matching FITNESS_DETAILS
+      const fitnessDetails = Buffer.alloc(12);
+      fitnessDetails.writeUInt8(1, 0);
+      fitnessDetails.writeUInt32LE(6000, 1);
+      fitnessDetails.writeUInt32LE(5000, 5);
+      fitnessDetails.writeUInt8(1, 9);
+      fitnessDetails.writeUInt8(140, 10);
+      fitnessDetails.writeUInt8(0, 11); // FitnessActivity::Run
+      const duration = Buffer.alloc(3);
+      duration.writeUInt8(1, 0);
+      duration.writeUInt16LE(durationMinutes, 1);
+      return Buffer.concat([
+        Buffer.from("disciplinator:session-attestation:v1"),
+        challenge.toBuffer(),
+        u32(sessionNumber),
+        Buffer.from([0]), // ProofInput::IpfsCid
+        u32(proofHash.length),
+        Buffer.from(proofHash),
+        duration,
+        Buffer.from([0]), // location: None
+        Buffer.from([0]), // notes: None
+        fitnessDetails,
+      ]);
+    };
+
+    before(async () => {
+      await provider.connection.requestAirdrop(relayer.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      [attesterRecordPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("attester"), attester.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .registerAttester(attester.publicKey)
+        .accounts({
+          attesterRecord: attesterRecordPda,
+          config: configPda,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      // The participant opts in by naming the attester as the challenge verifier
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [attestedChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          10,
+          30,
+          attester.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge: attestedChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+    });
+
+    it("Should accept a relayed attestation signed by a registered attester", async () => {
+      const proofHash = "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU";
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), attestedChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+
+      const ed25519Ix = Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: attester.secretKey,
+        message: encodeAttestation(attestedChallengePda, 1, proofHash, 40),
+      });
+
+      await program.methods
+        .markSessionAttested(1, { ipfsCid: { cid: proofHash } }, {
+          durationMinutes: 40,
+          location: null,
+          notes: null,
+          details: FITNESS_DETAILS
+        })
+        .accounts({
+          challenge: attestedChallengePda,
+          participant: participant.publicKey,
+          attesterRecord: attesterRecordPda,
+          payer: relayer.publicKey,
+          session: sessionPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+          systemProgram: SystemProgram.programId,
+        })
+        .preInstructions([ed25519Ix])
+        .signers([relayer])
+        .rpc();
+
+      const session = await program.account.session.fetch(sessionPda);
+      assert.equal(session.verifiedBy.toString(), attester.publicKey.toString());
+
+      const record = await program.account.attesterRecord.fetch(attesterRecordPda);
+      assert.equal(record.sessionsAttested.toNumber(), 1);
+    });
+
+    it("Should reject an attestation whose signed message does not match", async () => {
+      const proofHash = "QmTXKz6FwjhZGNKUjC2qv3XN7efdF8hSYrUwPxEGdBnNkS";
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), attestedChallengePda.toBuffer(), Buffer.from([1, 0, 0, 0])],
+        program.programId
+      );
+
+      // Signed for 20 minutes, submitted as 90
+      const ed25519Ix = Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: attester.secretKey,
+        message: encodeAttestation(attestedChallengePda, 2, proofHash, 20),
+      });
+
+      try {
+        await program.methods
+          .markSessionAttested(2, { ipfsCid: { cid: proofHash } }, {
+            durationMinutes: 90,
+            location: null,
+            notes: null,
+            details: FITNESS_DETAILS
+          })
+          .accounts({
+            challenge: attestedChallengePda,
+            participant: participant.publicKey,
+            attesterRecord: attesterRecordPda,
+            payer: relayer.publicKey,
+            session: sessionPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+            systemProgram: SystemProgram.programId,
+          })
+          .preInstructions([ed25519Ix])
+          .signers([relayer])
+          .rpc();
+
+        assert.fail("Should have rejected tampered attestation");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidAttestation");
+      }
+    });
+
+    it("Should not credit an attested session to someone else's stats", async () => {
+      const proofHash = "QmTXKz6FwjhZGNKUjC2qv3XN7efdF8hSYrUwPxEGdBnNkS";
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), attestedChallengePda.toBuffer(), Buffer.from([1, 0, 0, 0])],
+        program.programId
+      );
+      const relayerStatsPda = await openOwnChallenge(relayer);
+
+      const ed25519Ix = Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: attester.secretKey,
+        message: encodeAttestation(attestedChallengePda, 2, proofHash, 40),
+      });
+
+      try {
+        await program.methods
+          .markSessionAttested(2, { ipfsCid: { cid: proofHash } }, {
+            durationMinutes: 40,
+            location: null,
+            notes: null,
+            details: FITNESS_DETAILS
+          })
+          .accounts({
+            challenge: attestedChallengePda,
+            participant: relayer.publicKey,
+            attesterRecord: attesterRecordPda,
+            payer: relayer.publicKey,
+            session: sessionPda,
+            userStats: relayerStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+            systemProgram: SystemProgram.programId,
+          })
+          .preInstructions([ed25519Ix])
+          .signers([relayer])
+          .rpc();
+
+        assert.fail("Should have rejected a participant that does not own the challenge");
+      } catch (error) {
+        assert.include(error.toString(), "UnauthorizedParticipant");
+      }
+    });
+  });
+
+  describe("Batch Session Marking", () => {
+    const coach = Keypair.generate();
+    const batchChallengePdas: PublicKey[] = [];
+
+    before(async () => {
+      await provider.connection.requestAirdrop(coach.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      for (let i = 0; i < 2; i++) {
+        const challengeIndex = await nextChallengeIndex(participant.publicKey);
+        const [batchChallengePda] = PublicKey.findProgramAddressSync(
+          [
+            Buffer.from("challenge"),
+            participant.publicKey.toBuffer(),
+            challengeIndex.toArrayLike(Buffer, "le", 8)
+          ],
+          program.programId
+        );
+
+        await program.methods
+          .createChallenge(
+            new anchor.BN(TEST_DEPOSIT),
+            10,
+            30,
+            coach.publicKey,
+            MEDITATION_TYPE_ID,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
+          )
+          .accounts({
+            challenge: batchChallengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: meditationTypePda,
+            acceptedMint: mint,
+            vault: vaultPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([participant])
+          .rpc();
+
+        batchChallengePdas.push(batchChallengePda);
+      }
+    });
+
+    it("Should mark sessions for several challenges in one instruction", async () => {
+      const sessionPdas = batchChallengePdas.map(challenge =>
+        PublicKey.findProgramAddressSync(
+          [Buffer.from("session"), challenge.toBuffer(), Buffer.from([0, 0, 0, 0])],
+          program.programId
+        )[0]
+      );
+
+      await program.methods
+        .markSessionsBatch(
+          batchChallengePdas.map(challenge => ({
+            challenge,
+            proof: { ipfsCid: { cid: "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e" } }, // CIDv1, raw sha2-256
+            metadata: { durationMinutes: 15, location: null, notes: null, details: { meditation: { hrvMs: 62 } } },
+          }))
+        )
+        .accounts({
+          signer: coach.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(
+          batchChallengePdas.flatMap((challenge, i) => [
+            { pubkey: challenge, isWritable: true, isSigner: false },
+            { pubkey: sessionPdas[i], isWritable: true, isSigner: false },
+            { pubkey: userStatsPda, isWritable: true, isSigner: false },
+            { pubkey: statsShardFor(participant.publicKey), isWritable: true, isSigner: false },
+          ])
+        )
+        .signers([coach])
+        .rpc();
+
+      for (let i = 0; i < batchChallengePdas.length; i++) {
+        const challenge = await program.account.challenge.fetch(batchChallengePdas[i]);
+        assert.equal(challenge.completedSessions, 1);
+
+        const session = await program.account.session.fetch(sessionPdas[i]);
+        assert.equal(session.verifiedBy.toString(), coach.publicKey.toString());
+      }
+    });
+
+    it("Should let the verifier revoke a session before finalization", async () => {
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), batchChallengePdas[0].toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+      const statsBefore = await program.account.userStats.fetch(userStatsPda);
+
+      await program.methods
+        .revokeSession()
+        .accounts({
+          challenge: batchChallengePdas[0],
+          session: sessionPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          config: configPda,
+          signer: coach.publicKey,
+        })
+        .signers([coach])
+        .rpc();
+
+      const session = await program.account.session.fetch(sessionPda);
+      assert.isTrue(session.revoked);
+
+      const challenge = await program.account.challenge.fetch(batchChallengePdas[0]);
+      assert.equal(challenge.completedSessions, 0);
+      assert.equal(challenge.sessionsRecorded, 1);
+
+      const statsAfter = await program.account.userStats.fetch(userStatsPda);
+      assert.equal(statsAfter.totalSessionsCompleted, statsBefore.totalSessionsCompleted - 1);
+    });
+  });
+
+  describe("Compressed Sessions", () => {
+    const coach = Keypair.generate();
+    let compressedChallengePda: PublicKey;
+
+    before(async () => {
+      await provider.connection.requestAirdrop(coach.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [compressedChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(new anchor.BN(TEST_DEPOSIT), 10, 30, coach.publicKey, MEDITATION_TYPE_ID, { none: {} }, null, null, null, null, null)
+        .accounts({
+          challenge: compressedChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: meditationTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+    });
+
+    it("Should append sessions to a tree that can be rebuilt from events", async () => {
+      const [sessionTreePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session_tree"), compressedChallengePda.toBuffer()],
+        program.programId
+      );
+
+      const tx = await program.methods
+        .markSessionCompressed(
+          { sha256Digest: { digest: Array.from(sha256(Buffer.from("meditation log"))) } },
+          { durationMinutes: 20, location: null, notes: "evening sit", details: { meditation: { hrvMs: null } } }
+        )
+        .accounts({
+          challenge: compressedChallengePda,
+          participant: participant.publicKey,
+          signer: coach.publicKey,
+          sessionTree: sessionTreePda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([coach])
+        .rpc({ commitment: "confirmed" });
+
+      const txDetails = await provider.connection.getTransaction(tx, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const parser = new anchor.EventParser(program.programId, program.coder);
+      const appended = [...parser.parseLogs(txDetails.meta.logMessages)]
+        .filter(event => event.name === "sessionAppended")
+        .map(event => event.data as any);
+      assert.equal(appended.length, 1);
+
+      const leaves = appended.map(event =>
+        sha256(Buffer.from([0]), program.coder.types.encode("sessionLeaf", event.leaf))
+      );
+      const challenge = await program.account.challenge.fetch(compressedChallengePda);
+      assert.equal(challenge.compressedSessions, 1);
+      assert.equal(challenge.completedSessions, 1);
+      assert.deepEqual(Buffer.from(challenge.sessionRoot), rootFromLeaves(leaves));
+    });
+
+    it("Should not credit a compressed session to someone else's stats", async () => {
+      const [sessionTreePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session_tree"), compressedChallengePda.toBuffer()],
+        program.programId
+      );
+      const coachStatsPda = await openOwnChallenge(coach);
+
+      try {
+        await program.methods
+          .markSessionCompressed(
+            { sha256Digest: { digest: Array.from(sha256(Buffer.from("meditation log 2"))) } },
+            { durationMinutes: 20, location: null, notes: null, details: { meditation: { hrvMs: null } } }
+          )
+          .accounts({
+            challenge: compressedChallengePda,
+            participant: coach.publicKey,
+            signer: coach.publicKey,
+            sessionTree: sessionTreePda,
+            userStats: coachStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([coach])
+          .rpc();
+
+        assert.fail("Should have rejected a participant that does not own the challenge");
+      } catch (error) {
+        assert.include(error.toString(), "UnauthorizedParticipant");
+      }
+    });
+  });
+
+  describe("Grace Period", () => {
+    it("Should allow using a grace period", async () => {
+      const [gracePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("grace"),
+          challengePda.toBuffer(),
+          Buffer.from([0]) // grace_periods_used as u8
+        ],
+        program.programId
+      );
+
+      const before = await program.account.challenge.fetch(challengePda);
+      assert.equal(before.maxGracePeriods, 3);
+      assert.equal(before.gracePeriodDays, 3);
+
+      const tx = await program.methods
+        .useGracePeriod("Unexpected work commitment")
+        .accounts({
+          challenge: challengePda,
+          participant: participant.publicKey,
+          graceRecord: gracePda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+
+      console.log("Use grace period transaction signature:", tx);
+      
+      // Verify grace period was recorded
+      const graceRecord = await program.account.gracePeriodRecord.fetch(gracePda);
+      assert.equal(graceRecord.challenge.toString(), challengePda.toString());
+      assert.equal(graceRecord.reason, "Unexpected work commitment");
+      
+      // Verify challenge end time was extended
+      const challenge = await program.account.challenge.fetch(challengePda);
+      assert.equal(challenge.gracePeriodsUsed, 1);
+      assert.equal(
+        challenge.endTime.sub(before.endTime).toNumber(),
+        before.gracePeriodDays * 24 * 60 * 60
+      );
+    });
+
+    const gracePdaFor = (index: number) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("grace"), challengePda.toBuffer(), Buffer.from([index])],
+        program.programId
+      )[0];
+
+    const buyGracePeriod = (index: number, reason: string) =>
+      program.methods
+        .buyGracePeriod(reason)
+        .accounts({
+          challenge: challengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vaultRewards: vaultRewardsPda,
+          graceRecord: gracePdaFor(index),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+
+    it("Should not sell grace periods while free ones remain", async () => {
+      try {
+        await buyGracePeriod(1, "Trying to skip the quota");
+        assert.fail("Should have failed while free grace periods remain");
+      } catch (error) {
+        assert.include(error.message, "GracePeriodsStillAvailable");
+      }
+    });
+
+    it("Should sell extra grace periods into the reward pool at escalating prices", async () => {
+      // Spend the remaining free quota first
+      for (const index of [1, 2]) {
+        await program.methods
+          .useGracePeriod("Travel")
+          .accounts({
+            challenge: challengePda,
+            participant: participant.publicKey,
+            graceRecord: gracePdaFor(index),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([participant])
+          .rpc();
+      }
+
+      const config = await program.account.config.fetch(configPda);
+      const rewardsBalance = async () =>
+        (await getAccount(provider.connection, vaultRewardsPda, undefined, TOKEN_PROGRAM_ID)).amount;
+
+      const before = await rewardsBalance();
+      await buyGracePeriod(3, "Family emergency");
+      const afterFirst = await rewardsBalance();
+      await buyGracePeriod(4, "Still recovering");
+      const afterSecond = await rewardsBalance();
+
+      assert.equal((afterFirst - before).toString(), config.gracePriceBase.toString());
+      assert.equal(
+        (afterSecond - afterFirst).toString(),
+        config.gracePriceBase.add(config.gracePriceIncrement).toString()
+      );
+
+      const challenge = await program.account.challenge.fetch(challengePda);
+      assert.equal(challenge.gracePeriodsUsed, 5);
+      assert.equal(challenge.gracePeriodsPurchased, 2);
+      const record = await program.account.gracePeriodRecord.fetch(gracePdaFor(4));
+      assert.equal(record.reason, "Still recovering");
+    });
+  });
+
+  describe("Hardship Pause", () => {
+    let pauseChallengePda: PublicKey;
+    let pauseVerifier: Keypair;
+
+    before(async () => {
+      pauseVerifier = Keypair.generate();
+
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [pauseChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          10,
+          30,
+          pauseVerifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge: pauseChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+    });
+
+    it("Should let the participant withdraw a pause request", async () => {
+      await program.methods
+        .pauseChallenge("Flu")
+        .accounts({
+          challenge: pauseChallengePda,
+          participant: participant.publicKey,
+        })
+        .signers([participant])
+        .rpc();
+
+      await program.methods
+        .withdrawPauseRequest()
+        .accounts({
+          challenge: pauseChallengePda,
+          participant: participant.publicKey,
+        })
+        .signers([participant])
+        .rpc();
+
+      const withdrawn = await program.account.challenge.fetch(pauseChallengePda);
+      assert.equal(withdrawn.pauseRequestedAt.toNumber(), 0);
+
+      try {
+        await program.methods
+          .withdrawPauseRequest()
+          .accounts({
+            challenge: pauseChallengePda,
+            participant: participant.publicKey,
+          })
+          .signers([participant])
+          .rpc();
+        assert.fail("Should have failed without a pending request");
+      } catch (error) {
+        assert.include(error.message, "PauseNotRequested");
+      }
+    });
+
+    it("Should only let the verifier or arbiter approve a requested pause", async () => {
+      await program.methods
+        .pauseChallenge("Knee surgery")
+        .accounts({
+          challenge: pauseChallengePda,
+          participant: participant.publicKey,
+        })
+        .signers([participant])
+        .rpc();
+
+      const requested = await program.account.challenge.fetch(pauseChallengePda);
+      assert.isTrue(requested.status.active !== undefined); // Still active until approved
+      assert.isAbove(requested.pauseRequestedAt.toNumber(), 0);
+
+      const stranger = Keypair.generate();
+      try {
+        await program.methods
+          .approvePause()
+          .accounts({
+            challenge: pauseChallengePda,
+            config: configPda,
+            approver: stranger.publicKey,
+          })
+          .signers([stranger])
+          .rpc();
+        assert.fail("Should have failed for an unrelated approver");
+      } catch (error) {
+        assert.include(error.message, "Unauthorized");
+      }
+
+      await program.methods
+        .approvePause()
+        .accounts({
+          challenge: pauseChallengePda,
+          config: configPda,
+          approver: pauseVerifier.publicKey,
+        })
+        .signers([pauseVerifier])
+        .rpc();
+
+      const paused = await program.account.challenge.fetch(pauseChallengePda);
+      assert.isTrue(paused.status.paused !== undefined);
+      assert.equal(paused.pauseRequestedAt.toNumber(), 0);
+    });
+
+    it("Should reject sessions while paused", async () => {
+      const [pausedSessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), pauseChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .markSessionComplete(
+            { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+            { durationMinutes: 45, location: null, notes: null, details: FITNESS_DETAILS }
+          )
+          .accounts({
+            challenge: pauseChallengePda,
+            participant: participant.publicKey,
+            signer: pauseVerifier.publicKey,
+            session: pausedSessionPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([pauseVerifier])
+          .rpc();
+        assert.fail("Should have failed while the challenge is paused");
+      } catch (error) {
+        assert.include(error.message, "ChallengeNotActive");
+      }
+    });
+
+    it("Should only let a stranger resume once the maximum pause has passed", async () => {
+      const stranger = Keypair.generate();
+      try {
+        await program.methods
+          .resumeChallenge()
+          .accounts({
+            challenge: pauseChallengePda,
+            config: configPda,
+            caller: stranger.publicKey,
+          })
+          .signers([stranger])
+          .rpc();
+        assert.fail("Should have failed before the maximum pause");
+      } catch (error) {
+        assert.include(error.message, "Unauthorized");
+      }
+    });
+
+    it("Should shift the end time by the paused duration on resume", async () => {
+      const paused = await program.account.challenge.fetch(pauseChallengePda);
+      await new Promise(resolve => setTimeout(resolve, 2000));
+
+      await program.methods
+        .resumeChallenge()
+        .accounts({
+          challenge: pauseChallengePda,
+          config: configPda,
+          caller: participant.publicKey,
+        })
+        .signers([participant])
+        .rpc();
+
+      const resumed = await program.account.challenge.fetch(pauseChallengePda);
+      assert.isTrue(resumed.status.active !== undefined);
+      const shift = resumed.endTime.sub(paused.endTime).toNumber();
+      assert.isAbove(shift, 0);
+      assert.equal(shift, resumed.totalPausedSeconds.toNumber());
+    });
+  });
+
+  describe("Deferred Start", () => {
+    let deferredChallengePda: PublicKey;
+    let deferredVerifier: Keypair;
+
+    const createDeferredChallenge = async (startTime: anchor.BN) => {
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [challenge] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          10,
+          30,
+          deferredVerifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          startTime,
+          null, // single phase
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+      return challenge;
+    };
+
+    const now = () => Math.floor(Date.now() / 1000);
+
+    before(async () => {
+      deferredVerifier = Keypair.generate();
+    });
+
+    it("Should reject a start time beyond the configured horizon", async () => {
+      const config = await program.account.config.fetch(configPda);
+      try {
+        await createDeferredChallenge(new anchor.BN(now() + (config.maxStartDelayDays + 1) * 24 * 60 * 60));
+        assert.fail("Should have failed with a start time past the horizon");
+      } catch (error) {
+        assert.include(error.message, "InvalidStartTime");
+      }
+    });
+
+    it("Should escrow the deposit now and run from the start time", async () => {
+      const startTime = new anchor.BN(now() + 24 * 60 * 60); // Tomorrow
+      deferredChallengePda = await createDeferredChallenge(startTime);
+
+      const challenge = await program.account.challenge.fetch(deferredChallengePda);
+      assert.equal(challenge.startTime.toString(), startTime.toString());
+      assert.equal(challenge.endTime.sub(challenge.startTime).toNumber(), 30 * 24 * 60 * 60);
+    });
+
+    it("Should reject sessions before the start", async () => {
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), deferredChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .markSessionComplete(
+            { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+            { durationMinutes: 45, location: null, notes: null, details: FITNESS_DETAILS }
+          )
+          .accounts({
+            challenge: deferredChallengePda,
+            participant: participant.publicKey,
+            signer: deferredVerifier.publicKey,
+            session: sessionPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([deferredVerifier])
+          .rpc();
+        assert.fail("Should have failed before the start time");
+      } catch (error) {
+        assert.include(error.message, "ChallengeNotStarted");
+      }
+    });
+
+    it("Should refund the full deposit when cancelled before the start", async () => {
+      const balance = async () =>
+        (await getAccount(provider.connection, participantTokenAccount, undefined, TOKEN_PROGRAM_ID)).amount;
+      const before = await balance();
+
+      await program.methods
+        .cancelChallenge()
+        .accounts({
+          challenge: deferredChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([participant])
+        .rpc();
+
+      assert.equal(((await balance()) - before).toString(), TEST_DEPOSIT.toString());
+      const challenge = await program.account.challenge.fetch(deferredChallengePda);
+      assert.isTrue(challenge.status.cancelled !== undefined);
+    });
+
+    it("Should reject top-ups once the challenge is no longer active", async () => {
+      try {
+        await program.methods
+          .increaseDeposit(new anchor.BN(TEST_DEPOSIT))
+          .accounts({
+            challenge: deferredChallengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: fitnessTypePda,
+            template: null,
+            acceptedMint: mint,
+            vault: vaultPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([participant])
+          .rpc();
+        assert.fail("Should have failed for a cancelled challenge");
+      } catch (error) {
+        assert.include(error.message, "ChallengeNotActive");
+      }
+    });
+  });
+
+  describe("Phased Challenges", () => {
+    const phaseVerifier = Keypair.generate();
+    let phasedChallengePda: PublicKey;
+
+    before(async () => {
+      await provider.connection.requestAirdrop(phaseVerifier.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [phasedChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          4,
+          28,
+          phaseVerifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          [1, 1, 1, 1], // four weekly phases
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge: phasedChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+    });
+
+    const settlePhase = () =>
+      program.methods
+        .settlePhase()
+        .accounts({
+          challenge: phasedChallengePda,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          treasuryTokenAccount: treasuryTokenAccount,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    it("Should not settle a phase that is still running", async () => {
+      try {
+        await settlePhase();
+        assert.fail("Should have failed before the phase target or end");
+      } catch (error) {
+        assert.include(error.message, "PhaseNotOver");
+      }
+    });
+
+    it("Should release the phase's share of the deposit once its target is met", async () => {
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), phasedChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+      await program.methods
+        .markSessionComplete(
+          { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+          { durationMinutes: 30, location: null, notes: null, details: FITNESS_DETAILS }
+        )
+        .accounts({
+          challenge: phasedChallengePda,
+          participant: participant.publicKey,
+          signer: phaseVerifier.publicKey,
+          session: sessionPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([phaseVerifier])
+        .rpc();
+
+      const balance = async () =>
+        (await getAccount(provider.connection, participantTokenAccount, undefined, TOKEN_PROGRAM_ID)).amount;
+      const before = await balance();
+      await settlePhase();
+
+      assert.equal(((await balance()) - before).toString(), (TEST_DEPOSIT / 4).toString());
+      const challenge = await program.account.challenge.fetch(phasedChallengePda);
+      assert.equal(challenge.phaseIndex, 1);
+      assert.equal(challenge.phaseCompleted, 0);
+      assert.equal(challenge.refundedSoFar.toNumber(), TEST_DEPOSIT / 4);
+      assert.isTrue(challenge.status.active !== undefined);
+    });
+  });
+
+  describe("Milestone Refunds", () => {
+    const milestoneVerifier = Keypair.generate();
+    let milestoneChallengePda: PublicKey;
+
+    before(async () => {
+      await provider.connection.requestAirdrop(milestoneVerifier.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [milestoneChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          4,
+          28,
+          milestoneVerifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          2500 // a refund slice every 25% of sessions
+        )
+        .accounts({
+          challenge: milestoneChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+    });
+
+    const claimMilestoneRefund = () =>
+      program.methods
+        .claimMilestoneRefund()
+        .accounts({
+          challenge: milestoneChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([participant])
+        .rpc();
+
+    it("Should not pay out before the first milestone", async () => {
+      try {
+        await claimMilestoneRefund();
+        assert.fail("Should have failed with no milestone reached");
+      } catch (error) {
+        assert.include(error.message, "NoMilestoneRefund");
+      }
+    });
+
+    it("Should release a quarter of the deposit at the 25% milestone", async () => {
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), milestoneChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+      await program.methods
+        .markSessionComplete(
+          { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+          { durationMinutes: 30, location: null, notes: null, details: FITNESS_DETAILS }
+        )
+        .accounts({
+          challenge: milestoneChallengePda,
+          participant: participant.publicKey,
+          signer: milestoneVerifier.publicKey,
+          session: sessionPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([milestoneVerifier])
+        .rpc();
+
+      let challenge = await program.account.challenge.fetch(milestoneChallengePda);
+      assert.equal(challenge.milestonesPassed, 1);
+
+      const balance = async () =>
+        (await getAccount(provider.connection, participantTokenAccount, undefined, TOKEN_PROGRAM_ID)).amount;
+      const before = await balance();
+      await claimMilestoneRefund();
+
+      assert.equal(((await balance()) - before).toString(), (TEST_DEPOSIT / 4).toString());
+      challenge = await program.account.challenge.fetch(milestoneChallengePda);
+      assert.equal(challenge.refundedSoFar.toNumber(), TEST_DEPOSIT / 4);
+      assert.isTrue(challenge.status.active !== undefined);
+
+      try {
+        await claimMilestoneRefund();
+        assert.fail("Should not pay the same milestone twice");
+      } catch (error) {
+        assert.include(error.message, "NoMilestoneRefund");
+      }
+    });
+
+    it("Should not unlock passed milestones with a top-up", async () => {
+      await program.methods
+        .increaseDeposit(new anchor.BN(TEST_DEPOSIT))
+        .accounts({
+          challenge: milestoneChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          template: null,
+          acceptedMint: mint,
+          vault: vaultPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([participant])
+        .rpc();
+
+      const challenge = await program.account.challenge.fetch(milestoneChallengePda);
+      assert.equal(challenge.milestoneExcludedDeposit.toNumber(), TEST_DEPOSIT / 4);
+      try {
+        await claimMilestoneRefund();
+        assert.fail("Should not pay a passed milestone from the top-up");
+      } catch (error) {
+        assert.include(error.message, "NoMilestoneRefund");
+      }
+    });
+  });
+
+  describe("Challenge Templates", () => {
+    const coach = Keypair.generate();
+    const coachVerifier = Keypair.generate();
+    const templateId = new anchor.BN(1);
+    let coachRecordPda: PublicKey;
+    let templatePda: PublicKey;
+
+    const templateParams = {
+      name: "30-day couch to 5K",
+      challengeTypeId: FITNESS_TYPE_ID,
+      totalSessions: 12,
+      durationDays: 30,
+      sessionGoal: { none: {} },
+      schedule: null,
+      graceTerms: null,
+      graceRefundPenaltyBps: 1000, // 10% per free grace period, stricter than the type's 5%
+      phaseTargets: [],
+      verifiers: [coachVerifier.publicKey],
+      minDeposit: new anchor.BN(TEST_DEPOSIT),
+      maxDeposit: new anchor.BN(TEST_DEPOSIT * 10),
+    };
+
+    before(async () => {
+      await provider.connection.requestAirdrop(coach.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      [coachRecordPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("coach"), coach.publicKey.toBuffer()],
+        program.programId
+      );
+      [templatePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("template"), coach.publicKey.toBuffer(), templateId.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+    });
+
+    const createTemplate = (coachRecord: PublicKey | null) =>
+      program.methods
+        .createChallengeTemplate(templateId, templateParams)
+        .accounts({
+          template: templatePda,
+          coachRecord,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          creator: coach.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([coach])
+        .rpc();
+
+    const createFromTemplate = async (verifier: PublicKey) => {
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [challengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+      await program.methods
+        .createChallengeFromTemplate(new anchor.BN(TEST_DEPOSIT), verifier, null, null)
+        .accounts({
+          userStats: userStatsPda,
+          challenge: challengePda,
+          template: templatePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+      return challengePda;
+    };
+
+    it("Should not let an unregistered creator publish a template", async () => {
+      try {
+        await createTemplate(null);
+        assert.fail("Should have failed without a coach record");
+      } catch (error) {
+        assert.include(error.message, "Unauthorized");
+      }
+    });
+
+    it("Should let a registered coach publish a template", async () => {
+      await program.methods
+        .registerCoach(coach.publicKey)
+        .accounts({
+          coachRecord: coachRecordPda,
+          config: configPda,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      await createTemplate(coachRecordPda);
+
+      const template = await program.account.challengeTemplate.fetch(templatePda);
+      assert.equal(template.name, templateParams.name);
+      assert.equal(template.creator.toString(), coach.publicKey.toString());
+      assert.equal(template.timesUsed.toNumber(), 0);
+      assert.isTrue(template.active);
+    });
+
+    it("Should open a challenge with the template's terms", async () => {
+      const challengePda = await createFromTemplate(coachVerifier.publicKey);
+
+      const challenge = await program.account.challenge.fetch(challengePda);
+      assert.equal(challenge.totalSessions, 12);
+      assert.equal(challenge.durationDays, 30);
+      assert.equal(challenge.template.toString(), templatePda.toString());
+      assert.equal(challenge.verifier.toString(), coachVerifier.publicKey.toString());
+      assert.equal(challenge.graceRefundPenaltyBps, 1000);
+
+      const template = await program.account.challengeTemplate.fetch(templatePda);
+      assert.equal(template.timesUsed.toNumber(), 1);
+      assert.equal(template.runsCompleted.toNumber(), 0);
+    });
+
+    it("Should cap top-ups at the template's maximum deposit", async () => {
+      const challengePda = await createFromTemplate(coachVerifier.publicKey);
+      const increaseDeposit = (amount: anchor.BN, template: PublicKey | null) =>
+        program.methods
+          .increaseDeposit(amount)
+          .accounts({
+            challenge: challengePda,
+            participant: participant.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            challengeType: fitnessTypePda,
+            template,
+            acceptedMint: mint,
+            vault: vaultPda,
+            statsShard: statsShardFor(participant.publicKey),
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([participant])
+          .rpc();
+
+      // Within the type's range but above the template's
+      const aboveTemplate = new anchor.BN(TEST_DEPOSIT * 9 + 1);
+      try {
+        await increaseDeposit(aboveTemplate, templatePda);
+        assert.fail("Should have failed above the template's maximum deposit");
+      } catch (error) {
+        assert.include(error.message, "DepositTooLarge");
+      }
+
+      try {
+        await increaseDeposit(aboveTemplate, null);
+        assert.fail("Should have required the template account");
+      } catch (error) {
+        assert.include(error.message, "TemplateMismatch");
+      }
+    });
+
+    it("Should reject a verifier outside the template's set", async () => {
+      try {
+        await createFromTemplate(Keypair.generate().publicKey);
+        assert.fail("Should have failed with an unlisted verifier");
+      } catch (error) {
+        assert.include(error.message, "VerifierNotInTemplate");
+      }
+    });
+
+    it("Should stop accepting participants once deactivated", async () => {
+      await program.methods
+        .setTemplateActive(false)
+        .accounts({
+          template: templatePda,
+          creator: coach.publicKey,
+        })
+        .signers([coach])
+        .rpc();
+
+      try {
+        await createFromTemplate(coachVerifier.publicKey);
+        assert.fail("Should have failed on an inactive template");
+      } catch (error) {
+        assert.include(error.message, "TemplateInactive");
+      }
+    });
+  });
+
+  describe("Pledges", () => {
+    const supporter = Keypair.generate();
+    const PLEDGE = 2_000_000; // 2 USDT
+    let supporterTokenAccount: PublicKey;
+    let pledgedChallengePda: PublicKey;
+    let pledgePda: PublicKey;
+
+    before(async () => {
+      await provider.connection.requestAirdrop(supporter.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      supporterTokenAccount = await createAccount(
+        provider.connection,
+        supporter,
+        mint,
+        supporter.publicKey
+      );
+      await mintTo(
+        provider.connection,
+        authority,
+        mint,
+        supporterTokenAccount,
+        authority,
+        PLEDGE * 2
+      );
+
+      // Starts tomorrow so it can still be cancelled
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [pledgedChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+      [pledgePda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pledge"), pledgedChallengePda.toBuffer(), supporter.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          10,
+          30,
+          verifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          new anchor.BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60),
+          null, // single phase
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge: pledgedChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+    });
+
+    const supporterBalance = async () =>
+      (await getAccount(provider.connection, supporterTokenAccount, undefined, TOKEN_PROGRAM_ID)).amount;
+
+    const pledge = (backer: Keypair, tokenAccount: PublicKey, amount: number, challenge = pledgedChallengePda) =>
+      program.methods
+        .pledgeToChallenge(new anchor.BN(amount))
+        .accounts({
+          challenge,
+          pledge: PublicKey.findProgramAddressSync(
+            [Buffer.from("pledge"), challenge.toBuffer(), backer.publicKey.toBuffer()],
+            program.programId
+          )[0],
+          supporter: backer.publicKey,
+          supporterTokenAccount: tokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([backer])
+        .rpc();
+
+    it("Should not let participants pledge to their own challenge", async () => {
+      try {
+        await pledge(participant, participantTokenAccount, PLEDGE);
+        assert.fail("Should have failed with a self-pledge");
+      } catch (error) {
+        assert.include(error.message, "CannotPledgeOwnChallenge");
+      }
+    });
+
+    it("Should add repeat pledges to the supporter's pledge", async () => {
+      await pledge(supporter, supporterTokenAccount, PLEDGE);
+      await pledge(supporter, supporterTokenAccount, PLEDGE);
+
+      const pledgeAccount = await program.account.pledge.fetch(pledgePda);
+      assert.equal(pledgeAccount.amount.toNumber(), PLEDGE * 2);
+      const challenge = await program.account.challenge.fetch(pledgedChallengePda);
+      assert.equal(challenge.pledgedTotal.toNumber(), PLEDGE * 2);
+      assert.equal(challenge.pledgeCount, 1);
+      assert.equal((await supporterBalance()).toString(), "0");
+    });
+
+    it("Should refund pledges once the challenge is cancelled", async () => {
+      await program.methods
+        .cancelChallenge()
+        .accounts({
+          challenge: pledgedChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([participant])
+        .rpc();
+
+      await program.methods
+        .refundPledge()
+        .accounts({
+          challenge: pledgedChallengePda,
+          pledge: pledgePda,
+          supporter: supporter.publicKey,
+          supporterTokenAccount: supporterTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([supporter])
+        .rpc();
+
+      assert.equal((await supporterBalance()).toString(), (PLEDGE * 2).toString());
+      const challenge = await program.account.challenge.fetch(pledgedChallengePda);
+      assert.equal(challenge.pledgedTotal.toNumber(), 0);
+      assert.isNull(await provider.connection.getAccountInfo(pledgePda));
+    });
+
+    it("Should pay backed pledges to the participant at finalization", async () => {
+      const backedVerifier = Keypair.generate();
+      await provider.connection.requestAirdrop(backedVerifier.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      const [backedChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          participant.publicKey.toBuffer(),
+          challengeIndex.toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          1,
+          30,
+          backedVerifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge: backedChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+
+      await pledge(supporter, supporterTokenAccount, PLEDGE, backedChallengePda);
+
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), backedChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+      await program.methods
+        .markSessionComplete(
+          { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+          { durationMinutes: 30, location: null, notes: null, details: FITNESS_DETAILS }
+        )
+        .accounts({
+          challenge: backedChallengePda,
+          participant: participant.publicKey,
+          signer: backedVerifier.publicKey,
+          session: sessionPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([backedVerifier])
+        .rpc();
+
+      const balance = async () =>
+        (await getAccount(provider.connection, participantTokenAccount, undefined, TOKEN_PROGRAM_ID)).amount;
+      const before = await balance();
+
+      const [finalizationRecordPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("finalization"), backedChallengePda.toBuffer()],
+        program.programId
+      );
+      const finalize = (caller: Keypair) =>
+        program.methods
+          .finalizeChallenge()
+          .accounts({
+            challenge: backedChallengePda,
+            caller: caller.publicKey,
+            participantTokenAccount: participantTokenAccount,
+            config: configPda,
+            acceptedMint: mint,
+            vault: vaultPda,
+            treasuryTokenAccount: treasuryTokenAccount,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
+            finalizationRecord: finalizationRecordPda,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([caller])
+          .rpc();
+
+      // Anyone may finalize once the challenge ends, but only the participant before that
+      try {
+        await finalize(supporter);
+        assert.fail("Should have rejected early finalization by a supporter");
+      } catch (error) {
+        assert.include(error.message, "Unauthorized");
+      }
+      await finalize(participant);
+
+      // The full deposit comes back together with the supporter's pledge
+      assert.equal(((await balance()) - before).toString(), (TEST_DEPOSIT + PLEDGE).toString());
+      const challenge = await program.account.challenge.fetch(backedChallengePda);
+      assert.isTrue(challenge.status.completed !== undefined);
+      assert.equal(challenge.penalizedSoFar.toNumber(), 0);
+      const record = await program.account.finalizationRecord.fetch(finalizationRecordPda);
+      assert.isTrue(record.rewardPoolContribution.isZero());
+    });
+  });
+
+  describe("Protocol Controls", () => {
+    it("Should pause the protocol", async () => {
+      const tx = await program.methods
+        .pauseProtocol()
+        .accounts({
           config: configPda,
           authority: authority.publicKey,
         })
@@ -479,6 +2612,34 @@ describe("disciplinator", () => {
       assert.isTrue(config.paused);
     });
 
+    it("Should only let the authority set a positive maximum pause", async () => {
+      try {
+        await program.methods
+          .setMaxPauseDuration(0)
+          .accounts({
+            config: configPda,
+            authority: authority.publicKey,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have rejected a zero maximum pause");
+      } catch (error) {
+        assert.include(error.message, "InvalidPauseDuration");
+      }
+
+      await program.methods
+        .setMaxPauseDuration(60)
+        .accounts({
+          config: configPda,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+
+      const config = await program.account.config.fetch(configPda);
+      assert.equal(config.maxPauseDays, 60);
+    });
+
     it("Should unpause the protocol", async () => {
       const tx = await program.methods
         .unpauseProtocol()
@@ -514,19 +2675,22 @@ describe("disciplinator", () => {
   });
 
   describe("Finalization", () => {
+    const testVerifier = Keypair.generate();
+    let finalizationChallengePda: PublicKey;
+    let finalizationPda: PublicKey;
+
     it("Should finalize a challenge", async () => {
-      // Create a verifier for this test
-      const testVerifier = Keypair.generate();
+      // Fund the verifier for this test
       await provider.connection.requestAirdrop(testVerifier.publicKey, LAMPORTS_PER_SOL);
       await new Promise(resolve => setTimeout(resolve, 500));
       
       // Create a new challenge specifically for finalization testing
-      const configAccount = await program.account.config.fetch(configPda);
-      const [finalizationChallengePda] = PublicKey.findProgramAddressSync(
+      const challengeIndex = await nextChallengeIndex(participant.publicKey);
+      [finalizationChallengePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           participant.publicKey.toBuffer(),
-          configAccount.totalChallenges.toArrayLike(Buffer, "le", 8)
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       );
@@ -538,23 +2702,31 @@ describe("disciplinator", () => {
           1, // Only 1 session to avoid SessionTooSoon errors
           30, // 30 days
           testVerifier.publicKey, // With our test verifier
-          { fitness: {} }
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
         )
         .accounts({
           challenge: finalizationChallengePda,
           participant: participant.publicKey,
           participantTokenAccount: participantTokenAccount,
           config: configPda,
+          challengeType: fitnessTypePda,
           acceptedMint: mint,
           vault: vaultPda,
           userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
         .signers([participant])
         .rpc();
       
-      const [finalizationPda] = PublicKey.findProgramAddressSync(
+      [finalizationPda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("finalization"),
           finalizationChallengePda.toBuffer()
@@ -576,11 +2748,12 @@ describe("disciplinator", () => {
         
         await program.methods
           .markSessionComplete(
-            "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU", // Valid IPFS hash (same for all for simplicity)
+            { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } }, // Valid IPFS hash (same for all for simplicity)
             {
               durationMinutes: 30,
               location: "Test Location",
-              notes: "Test session " + i
+              notes: "Test session " + i,
+              details: FITNESS_DETAILS
             }
           )
           .accounts({
@@ -589,6 +2762,7 @@ describe("disciplinator", () => {
             signer: testVerifier.publicKey,
             session: currentSessionPda,
             userStats: userStatsPda,
+            statsShard: statsShardFor(participant.publicKey),
             systemProgram: SystemProgram.programId,
           })
           .signers([testVerifier])
@@ -603,13 +2777,14 @@ describe("disciplinator", () => {
         .finalizeChallenge()
         .accounts({
           challenge: finalizationChallengePda,
-          participant: participant.publicKey,
+          caller: participant.publicKey,
           participantTokenAccount: participantTokenAccount,
           config: configPda,
           acceptedMint: mint,
           vault: vaultPda,
           treasuryTokenAccount: treasuryTokenAccount,
           userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
           finalizationRecord: finalizationPda,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
@@ -623,11 +2798,227 @@ describe("disciplinator", () => {
       const finalizationRecord = await program.account.finalizationRecord.fetch(finalizationPda);
       assert.equal(finalizationRecord.challenge.toString(), finalizationChallengePda.toString());
       assert.equal(finalizationRecord.participant.toString(), participant.publicKey.toString());
+      assert.equal(finalizationRecord.rentPayer.toString(), participant.publicKey.toString());
       
       // Verify challenge status was updated
       const challenge = await program.account.challenge.fetch(finalizationChallengePda);
       assert.isNotNull(challenge.status.failed || challenge.status.partiallyCompleted);
     });
+
+    it("Should keep sessions until the dispute window passes", async () => {
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), finalizationChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .closeSession()
+          .accounts({
+            challenge: finalizationChallengePda,
+            session: sessionPda,
+            rentPayer: testVerifier.publicKey,
+          })
+          .signers([testVerifier])
+          .rpc();
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "DisputeWindowOpen");
+      }
+
+      // The session stays available as evidence while the outcome can still be disputed
+      assert.isNotNull(await provider.connection.getAccountInfo(sessionPda));
+    });
+
+    it("Should close a finalization record with no reward pool contribution", async () => {
+      const record = await program.account.finalizationRecord.fetch(finalizationPda);
+      assert.isTrue(record.rewardPoolContribution.isZero());
+
+      await program.methods
+        .closeFinalizationRecord()
+        .accounts({
+          finalizationRecord: finalizationPda,
+          rewardState: rewardStatePda,
+          rentPayer: participant.publicKey,
+        })
+        .signers([participant])
+        .rpc();
+
+      assert.isNull(await provider.connection.getAccountInfo(finalizationPda));
+    });
+
+    it("Should keep a finalized challenge open during the dispute window", async () => {
+      try {
+        await program.methods
+          .closeChallenge()
+          .accounts({
+            challenge: finalizationChallengePda,
+            userStats: userStatsPda,
+            participant: participant.publicKey,
+          })
+          .signers([participant])
+          .rpc();
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "DisputeWindowOpen");
+      }
+    });
+  });
+
+  describe("Recurring Challenges", () => {
+    const recurringVerifier = Keypair.generate();
+    let recurringChallengePda: PublicKey;
+    let renewedChallengePda: PublicKey;
+
+    const challengePdaAt = (index: anchor.BN) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("challenge"), participant.publicKey.toBuffer(), index.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0];
+
+    // Participants approve this PDA as delegate to fund renewals beyond the rollover
+    const renewalAuthority = () =>
+      PublicKey.findProgramAddressSync([Buffer.from("renewal_authority"), configPda.toBuffer()], program.programId)[0];
+
+    const renew = (previous: PublicKey, next: PublicKey) =>
+      program.methods
+        .renewChallenge()
+        .accounts({
+          previousChallenge: previous,
+          userStats: userStatsPda,
+          nextChallenge: next,
+          keeper: authority.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          renewalAuthority: renewalAuthority(),
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+    it("Should keep the refund of a recurring challenge for the next run", async () => {
+      recurringChallengePda = challengePdaAt(await nextChallengeIndex(participant.publicKey));
+
+      await program.methods
+        .createChallenge(
+          new anchor.BN(TEST_DEPOSIT),
+          1,
+          30,
+          recurringVerifier.publicKey,
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
+        )
+        .accounts({
+          challenge: recurringChallengePda,
+          participant: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          challengeType: fitnessTypePda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+
+      await program.methods
+        .setRecurring(true)
+        .accounts({ challenge: recurringChallengePda, participant: participant.publicKey })
+        .signers([participant])
+        .rpc();
+
+      await provider.connection.requestAirdrop(recurringVerifier.publicKey, LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 500));
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), recurringChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+      await program.methods
+        .markSessionComplete(
+          { ipfsCid: { cid: "QmNRCQX8gEZsRp1UnD3mNsGj5hCKjFkWqWkYpGjkhU4QgU" } },
+          { durationMinutes: 30, location: null, notes: null, details: FITNESS_DETAILS }
+        )
+        .accounts({
+          challenge: recurringChallengePda,
+          participant: participant.publicKey,
+          signer: recurringVerifier.publicKey,
+          session: sessionPda,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([recurringVerifier])
+        .rpc();
+
+      const balance = async () =>
+        (await getAccount(provider.connection, participantTokenAccount, undefined, TOKEN_PROGRAM_ID)).amount;
+      const before = await balance();
+
+      const [finalizationRecordPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("finalization"), recurringChallengePda.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .finalizeChallenge()
+        .accounts({
+          challenge: recurringChallengePda,
+          caller: participant.publicKey,
+          participantTokenAccount: participantTokenAccount,
+          config: configPda,
+          acceptedMint: mint,
+          vault: vaultPda,
+          treasuryTokenAccount: treasuryTokenAccount,
+          userStats: userStatsPda,
+          statsShard: statsShardFor(participant.publicKey),
+          finalizationRecord: finalizationRecordPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([participant])
+        .rpc();
+
+      assert.equal((await balance()).toString(), before.toString());
+      const challenge = await program.account.challenge.fetch(recurringChallengePda);
+      assert.equal(challenge.rolloverAmount.toNumber(), TEST_DEPOSIT);
+    });
+
+    it("Should renew with the same terms funded by the rollover", async () => {
+      renewedChallengePda = challengePdaAt(await nextChallengeIndex(participant.publicKey));
+      await renew(recurringChallengePda, renewedChallengePda);
+
+      const previous = await program.account.challenge.fetch(recurringChallengePda);
+      const next = await program.account.challenge.fetch(renewedChallengePda);
+      assert.isFalse(previous.recurring);
+      assert.equal(previous.rolloverAmount.toNumber(), 0);
+      assert.isTrue(next.recurring);
+      assert.isTrue(next.status.active !== undefined);
+      assert.equal(next.depositAmount.toNumber(), TEST_DEPOSIT);
+      assert.equal(next.totalSessions, previous.totalSessions);
+      assert.equal(next.completedSessions, 0);
+      assert.equal(next.endTime.sub(next.startTime).toNumber(), 30 * 24 * 60 * 60);
+    });
+
+    it("Should not renew the same challenge twice", async () => {
+      try {
+        await renew(recurringChallengePda, challengePdaAt(await nextChallengeIndex(participant.publicKey)));
+        assert.fail("Should have failed for an already renewed challenge");
+      } catch (error) {
+        assert.include(error.message, "NotRenewable");
+      }
+    });
   });
 
   describe("Security Tests", () => {
@@ -663,14 +3054,14 @@ describe("disciplinator", () => {
 
     it("Should prevent unauthorized session marking (verifier-only)", async () => {
       // Get current challenge count
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       
       // Create a challenge first
       const maliciousChallengePda = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          configAccount.totalChallenges.toArrayLike(Buffer, "le", 8)
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       )[0];
@@ -691,16 +3082,24 @@ describe("disciplinator", () => {
           21,
           30,
           verifier.publicKey, // Set verifier
-          { fitness: {} }
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
         )
         .accounts({
           challenge: maliciousChallengePda,
           participant: maliciousUser.publicKey,
           participantTokenAccount: maliciousTokenAccount,
           config: configPda,
+          challengeType: fitnessTypePda,
           acceptedMint: mint,
           vault: vaultPda,
           userStats: maliciousUserStatsPda,
+          statsShard: statsShardFor(maliciousUser.publicKey),
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
@@ -720,11 +3119,12 @@ describe("disciplinator", () => {
       try {
         await program.methods
           .markSessionComplete(
-            "QmTest123456789012345678901234567890123456", // Valid IPFS hash format
+            { ipfsCid: { cid: "QmTest123456789012345678901234567890123456" } }, // Valid IPFS hash format
             {
               durationMinutes: 30,
               location: "Test Location",
-              notes: "Self-verification attempt"
+              notes: "Self-verification attempt",
+              details: FITNESS_DETAILS
             }
           )
           .accounts({
@@ -733,6 +3133,7 @@ describe("disciplinator", () => {
             signer: maliciousUser.publicKey, // Participant trying to self-verify
             session: sessionPda,
             userStats: maliciousUserStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
             systemProgram: SystemProgram.programId,
           })
           .signers([maliciousUser])
@@ -744,14 +3145,58 @@ describe("disciplinator", () => {
       }
     });
 
+    it("Should not let a verifier credit a session to someone else's stats", async () => {
+      // The malicious user's first challenge names `verifier`, so only the participant account is wrong
+      const [maliciousChallengePda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("challenge"),
+          maliciousUser.publicKey.toBuffer(),
+          new anchor.BN(0).toArrayLike(Buffer, "le", 8)
+        ],
+        program.programId
+      );
+      const [sessionPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), maliciousChallengePda.toBuffer(), Buffer.from([0, 0, 0, 0])],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .markSessionComplete(
+            { ipfsCid: { cid: "QmTest123456789012345678901234567890123456" } },
+            {
+              durationMinutes: 30,
+              location: null,
+              notes: null,
+              details: FITNESS_DETAILS
+            }
+          )
+          .accounts({
+            challenge: maliciousChallengePda,
+            participant: participant.publicKey,
+            signer: verifier.publicKey,
+            session: sessionPda,
+            userStats: userStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([verifier])
+          .rpc();
+
+        assert.fail("Should have rejected a participant that does not own the challenge");
+      } catch (error) {
+        assert.include(error.toString(), "UnauthorizedParticipant");
+      }
+    });
+
     it("Should reject invalid IPFS hash format", async () => {
       // Create a challenge for this test
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       const [testChallengePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          configAccount.totalChallenges.toArrayLike(Buffer, "le", 8)
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       );
@@ -763,19 +3208,27 @@ describe("disciplinator", () => {
           10,
           30,
           participant.publicKey, // verifier
-          { fitness: {} }
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
         )
         .accounts({
           challenge: testChallengePda,
           participant: maliciousUser.publicKey,
           participantTokenAccount: maliciousTokenAccount,
           config: configPda,
+          challengeType: fitnessTypePda,
           acceptedMint: mint,
           vault: vaultPda,
           userStats: PublicKey.findProgramAddressSync(
             [Buffer.from("user_stats"), maliciousUser.publicKey.toBuffer()],
             program.programId
           )[0],
+          statsShard: statsShardFor(maliciousUser.publicKey),
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
@@ -794,11 +3247,12 @@ describe("disciplinator", () => {
       try {
         await program.methods
           .markSessionComplete(
-            "invalid_hash", // Invalid IPFS hash
+            { ipfsCid: { cid: "invalid_hash" } }, // Invalid IPFS hash
             {
               durationMinutes: 30,
               location: "Test Location",
-              notes: "Test session"
+              notes: "Test session",
+              details: FITNESS_DETAILS
             }
           )
           .accounts({
@@ -810,6 +3264,7 @@ describe("disciplinator", () => {
               [Buffer.from("user_stats"), maliciousUser.publicKey.toBuffer()],
               program.programId
             )[0],
+            statsShard: statsShardFor(maliciousUser.publicKey),
             systemProgram: SystemProgram.programId,
           })
           .signers([participant])
@@ -819,15 +3274,47 @@ describe("disciplinator", () => {
       } catch (error) {
         assert.include(error.toString(), "InvalidIPFSHash");
       }
+
+      // Off-IPFS evidence must be served over https
+      try {
+        await program.methods
+          .markSessionComplete(
+            { urlWithDigest: { url: "http://evidence.example.com/42.json", sha256: Array(32).fill(7) } },
+            {
+              durationMinutes: 30,
+              location: null,
+              notes: null,
+              details: FITNESS_DETAILS
+            }
+          )
+          .accounts({
+            challenge: testChallengePda,
+            participant: maliciousUser.publicKey,
+            signer: participant.publicKey,
+            session: sessionPda,
+            userStats: PublicKey.findProgramAddressSync(
+              [Buffer.from("user_stats"), maliciousUser.publicKey.toBuffer()],
+              program.programId
+            )[0],
+            statsShard: statsShardFor(maliciousUser.publicKey),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([participant])
+          .rpc();
+
+        assert.fail("Should have rejected non-https proof URL");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidProofUrl");
+      }
     });
 
     it("Should reject challenges with invalid session counts", async () => {
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       const invalidChallengePda = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          Buffer.from(configAccount.totalChallenges.toArrayLike(Buffer, "le", 8))
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       )[0];
@@ -844,16 +3331,24 @@ describe("disciplinator", () => {
             500, // Too many sessions
             30,
             verifier.publicKey,
-            { fitness: {} }
+            FITNESS_TYPE_ID,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
           )
           .accounts({
             challenge: invalidChallengePda,
             participant: maliciousUser.publicKey,
             participantTokenAccount: maliciousTokenAccount,
             config: configPda,
+            challengeType: fitnessTypePda,
             acceptedMint: mint,
             vault: vaultPda,
             userStats: maliciousUserStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
           })
@@ -867,12 +3362,12 @@ describe("disciplinator", () => {
     });
 
     it("Should reject challenges with invalid duration", async () => {
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       const invalidChallengePda = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          Buffer.from(configAccount.totalChallenges.toArrayLike(Buffer, "le", 8))
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       )[0];
@@ -889,16 +3384,24 @@ describe("disciplinator", () => {
             21,
             500, // Too many days
             verifier.publicKey,
-            { fitness: {} }
+            FITNESS_TYPE_ID,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
           )
           .accounts({
             challenge: invalidChallengePda,
             participant: maliciousUser.publicKey,
             participantTokenAccount: maliciousTokenAccount,
             config: configPda,
+            challengeType: fitnessTypePda,
             acceptedMint: mint,
             vault: vaultPda,
             userStats: maliciousUserStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
           })
@@ -912,12 +3415,12 @@ describe("disciplinator", () => {
     });
 
     it("Should reject deposit amounts that are too large", async () => {
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       const invalidChallengePda = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          Buffer.from(configAccount.totalChallenges.toArrayLike(Buffer, "le", 8))
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       )[0];
@@ -934,16 +3437,24 @@ describe("disciplinator", () => {
             21,
             30,
             verifier.publicKey,
-            { fitness: {} }
+            FITNESS_TYPE_ID,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
           )
           .accounts({
             challenge: invalidChallengePda,
             participant: maliciousUser.publicKey,
             participantTokenAccount: maliciousTokenAccount,
             config: configPda,
+            challengeType: fitnessTypePda,
             acceptedMint: mint,
             vault: vaultPda,
             userStats: maliciousUserStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
           })
@@ -957,12 +3468,12 @@ describe("disciplinator", () => {
     });
 
     it("Should reject deposit amounts that are too small", async () => {
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       const invalidChallengePda = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          Buffer.from(configAccount.totalChallenges.toArrayLike(Buffer, "le", 8))
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       )[0];
@@ -979,16 +3490,24 @@ describe("disciplinator", () => {
             21,
             30,
             verifier.publicKey,
-            { fitness: {} }
+            FITNESS_TYPE_ID,
+            { none: {} }, // no session goal
+            null, // default schedule
+            null, // default grace terms
+            null, // start immediately
+            null, // single phase
+            null // no milestone refunds
           )
           .accounts({
             challenge: invalidChallengePda,
             participant: maliciousUser.publicKey,
             participantTokenAccount: maliciousTokenAccount,
             config: configPda,
+            challengeType: fitnessTypePda,
             acceptedMint: mint,
             vault: vaultPda,
             userStats: maliciousUserStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
           })
@@ -1020,13 +3539,13 @@ describe("disciplinator", () => {
 
     it("Should create challenge without verifier and reject self-verification", async () => {
       // Get current challenge count
-      const configAccount = await program.account.config.fetch(configPda);
+      const challengeIndex = await nextChallengeIndex(maliciousUser.publicKey);
       
       const noVerifierChallengePda = PublicKey.findProgramAddressSync(
         [
           Buffer.from("challenge"),
           maliciousUser.publicKey.toBuffer(),
-          configAccount.totalChallenges.toArrayLike(Buffer, "le", 8)
+          challengeIndex.toArrayLike(Buffer, "le", 8)
         ],
         program.programId
       )[0];
@@ -1043,16 +3562,24 @@ describe("disciplinator", () => {
           21,
           30,
           null, // No verifier
-          { fitness: {} }
+          FITNESS_TYPE_ID,
+          { none: {} }, // no session goal
+          null, // default schedule
+          null, // default grace terms
+          null, // start immediately
+          null, // single phase
+          null // no milestone refunds
         )
         .accounts({
           challenge: noVerifierChallengePda,
           participant: maliciousUser.publicKey,
           participantTokenAccount: maliciousTokenAccount,
           config: configPda,
+          challengeType: fitnessTypePda,
           acceptedMint: mint,
           vault: vaultPda,
           userStats: maliciousUserStatsPda,
+          statsShard: statsShardFor(maliciousUser.publicKey),
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
@@ -1072,11 +3599,12 @@ describe("disciplinator", () => {
       try {
         await program.methods
           .markSessionComplete(
-            "QmTest123456789012345678901234567890123456",
+            { ipfsCid: { cid: "QmTest123456789012345678901234567890123456" } },
             {
               durationMinutes: 30,
               location: "Test Location",
-              notes: "Test session"
+              notes: "Test session",
+              details: FITNESS_DETAILS
             }
           )
           .accounts({
@@ -1085,6 +3613,7 @@ describe("disciplinator", () => {
             signer: maliciousUser.publicKey,
             session: sessionPda,
             userStats: maliciousUserStatsPda,
+            statsShard: statsShardFor(maliciousUser.publicKey),
             systemProgram: SystemProgram.programId,
           })
           .signers([maliciousUser])